@@ -0,0 +1,276 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde_json::Value;
+
+use crate::{
+    commit,
+    index::{Entry, Index},
+    shard, temporary,
+};
+
+/// Appends galleries to the last shard and starts new ones as shards fill up.
+///
+/// A shard is written to a temporary file, with the existing one copied in
+/// first, and renamed into place once closed, so every shard on disk always
+/// ends in its `]`. The index is saved after each shard, still sorted by gid.
+/// Should that save not happen, opening the archive again finds the shard out
+/// of step with the index and indexes it anew, see [`Index::reconcile`].
+pub struct Appender {
+    dir: PathBuf,
+    index_path: PathBuf,
+    index: Index,
+    max_items: usize,
+    /// gids already in the archive or appended in this run.
+    archived: HashSet<u64>,
+    /// The last shard on disk, extended first if it has room.
+    last: Option<(u32, PathBuf)>,
+    /// Number of the next new shard.
+    next: u32,
+    current: Option<Open>,
+    pub appended: usize,
+    pub skipped: usize,
+}
+
+/// The shard being written.
+struct Open {
+    path: PathBuf,
+    tmp: PathBuf,
+    out: BufWriter<File>,
+    file: u16,
+    count: usize,
+    /// Bytes written so far.
+    offset: u64,
+}
+
+impl Appender {
+    pub fn open(dir: &Path, index_path: &Path, max_items: usize) -> anyhow::Result<Self> {
+        let shards = shard::list(dir)?;
+        let index = match Index::load(index_path)? {
+            Some(mut index) => {
+                index.reconcile(dir, index_path)?;
+                index
+            }
+            None if shards.is_empty() => Index::default(),
+            None => anyhow::bail!(
+                "{} is missing, build it with `archive index` first",
+                index_path.display()
+            ),
+        };
+        Ok(Self {
+            dir: dir.to_owned(),
+            index_path: index_path.to_owned(),
+            archived: index.gids(),
+            index,
+            max_items,
+            next: shards.last().map_or(0, |v| v.0 + 1),
+            last: shards.last().cloned(),
+            current: None,
+            appended: 0,
+            skipped: 0,
+        })
+    }
+
+    /// Appends `item` unless its gid is already archived.
+    pub fn push(&mut self, item: Value) -> anyhow::Result<()> {
+        let gid = item["gid"]
+            .as_u64()
+            .with_context(|| format!("gallery without a gid: {item}"))?;
+        if !self.archived.insert(gid) {
+            self.skipped += 1;
+            return Ok(());
+        }
+        if self
+            .current
+            .as_ref()
+            .is_none_or(|v| v.count >= self.max_items)
+        {
+            self.close()?;
+            self.current = Some(self.start()?);
+        }
+        let current = self.current.as_mut().unwrap();
+        let separator: &[u8] = if current.count > 0 { b",\n" } else { b"\n" };
+        current.out.write_all(separator)?;
+        current.offset += separator.len() as u64;
+        let json = shard::canonical(&item);
+        current.out.write_all(json.as_bytes())?;
+        self.index.entries.push(Entry {
+            gid,
+            file: current.file,
+            offset: current.offset,
+            size: json.len() as u64,
+        });
+        current.offset += json.len() as u64;
+        current.count += 1;
+        self.appended += 1;
+        Ok(())
+    }
+
+    /// Closes the shard being written and saves the index.
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.close()
+    }
+
+    /// Opens the last shard if it has room, otherwise a new one.
+    fn start(&mut self) -> anyhow::Result<Open> {
+        if let Some((number, path)) = self.last.take() {
+            let count = shard::count(BufReader::new(File::open(&path)?))
+                .with_context(|| format!("reading {}", path.display()))?;
+            if count < self.max_items {
+                return self.extend(number, path, count);
+            }
+        }
+        let number = self.next;
+        self.next += 1;
+        let path = self.dir.join(shard::name(number));
+        let tmp = temporary(&path);
+        std::fs::create_dir_all(&self.dir)?;
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        out.write_all(b"[")?;
+        Ok(Open {
+            file: self.index.file_id(&shard::name(number)),
+            path,
+            tmp,
+            out,
+            count: 0,
+            offset: 1,
+        })
+    }
+
+    /// Copies the shard up to its closing `]` to a temporary file to append to.
+    fn extend(&mut self, number: u32, path: PathBuf, count: usize) -> anyhow::Result<Open> {
+        let mut file = File::open(&path)?;
+        let end =
+            shard::body_end(&mut file).with_context(|| format!("reading {}", path.display()))?;
+        file.seek(SeekFrom::Start(0))?;
+        let tmp = temporary(&path);
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        io::copy(&mut file.take(end), &mut out)?;
+        Ok(Open {
+            file: self.index.file_id(&shard::name(number)),
+            path,
+            tmp,
+            out,
+            count,
+            offset: end,
+        })
+    }
+
+    fn close(&mut self) -> anyhow::Result<()> {
+        let Some(mut current) = self.current.take() else {
+            return Ok(());
+        };
+        current.out.write_all(b"\n]\n")?;
+        commit(current.out.into_inner()?, &current.tmp, &current.path)?;
+        self.index.entries.sort_by_key(|v| v.gid);
+        self.index.save(&self.index_path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env::temp_dir,
+        fs::{self, read, read_to_string},
+        process,
+    };
+
+    use serde_json::{Value, json};
+
+    use super::Appender;
+    use crate::index::Index;
+
+    #[test]
+    fn appends_across_shards() {
+        let dir = temp_dir().join(format!("ehdump-append-{}", process::id()));
+        let archive = dir.join("archive");
+        let index_path = dir.join("item_index.bin");
+        fs::create_dir_all(&archive).unwrap();
+        fs::write(
+            archive.join("archive_0.json"),
+            "[\n{\"gid\":1,\"title\":\"a\"}\n]",
+        )
+        .unwrap();
+        // The shard is there but not the index.
+        assert!(Appender::open(&archive, &index_path, 3).is_err());
+        fs::remove_file(archive.join("archive_0.json")).unwrap();
+
+        let item = |gid: u64| json!({ "title": "ü", "gid": gid });
+        let mut appender = Appender::open(&archive, &index_path, 3).unwrap();
+        appender.push(item(1)).unwrap();
+        appender.finish().unwrap();
+
+        let mut appender = Appender::open(&archive, &index_path, 3).unwrap();
+        for gid in [2, 1, 3, 4, 5, 5] {
+            appender.push(item(gid)).unwrap();
+        }
+        assert_eq!((appender.appended, appender.skipped), (4, 2));
+        appender.finish().unwrap();
+
+        let shards = [
+            read_to_string(archive.join("archive_0.json")).unwrap(),
+            read_to_string(archive.join("archive_1.json")).unwrap(),
+        ];
+        assert_eq!(
+            shards[0],
+            "[\n{\"gid\":1,\"title\":\"ü\"},\n{\"gid\":2,\"title\":\"ü\"},\n{\"gid\":3,\"title\":\"ü\"}\n]\n"
+        );
+        for shard in &shards {
+            serde_json::from_str::<Vec<Value>>(shard).unwrap();
+        }
+        assert_eq!(fs::read_dir(&archive).unwrap().count(), 2);
+
+        let index = Index::load(&index_path).unwrap().unwrap();
+        assert_eq!(index.files, vec!["archive_0.json", "archive_1.json"]);
+        assert_eq!(
+            index.entries.iter().map(|v| v.gid).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4, 5]
+        );
+        for entry in index.entries {
+            let shard = read(archive.join(&index.files[usize::from(entry.file)])).unwrap();
+            let bytes = &shard[entry.offset as usize..][..entry.size as usize];
+            let item: Value = serde_json::from_slice(bytes).unwrap();
+            assert_eq!(item["gid"], entry.gid);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn recovers_unsaved_index() {
+        let dir = temp_dir().join(format!("ehdump-append-crash-{}", process::id()));
+        let archive = dir.join("archive");
+        let index_path = dir.join("item_index.bin");
+        let item = |gid: u64| json!({ "gid": gid });
+        let mut appender = Appender::open(&archive, &index_path, 3).unwrap();
+        appender.push(item(1)).unwrap();
+        appender.finish().unwrap();
+        let saved = read(&index_path).unwrap();
+        let mut appender = Appender::open(&archive, &index_path, 3).unwrap();
+        appender.push(item(2)).unwrap();
+        appender.finish().unwrap();
+        // As if the run died after the shard was renamed into place but
+        // before the index was saved.
+        fs::write(&index_path, saved).unwrap();
+
+        let mut appender = Appender::open(&archive, &index_path, 3).unwrap();
+        appender.push(item(2)).unwrap();
+        appender.push(item(3)).unwrap();
+        assert_eq!((appender.appended, appender.skipped), (1, 1));
+        appender.finish().unwrap();
+        assert_eq!(
+            read_to_string(archive.join("archive_0.json")).unwrap(),
+            "[\n{\"gid\":1},\n{\"gid\":2},\n{\"gid\":3}\n]\n"
+        );
+        assert_eq!(
+            Index::load(&index_path).unwrap().unwrap(),
+            Index::build(&archive).unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
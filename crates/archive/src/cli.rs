@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, builder::TypedValueParser};
+use ehdump_archive::shard::MAX_ITEMS;
+
+/// Maintains the archive shards and item_index.bin.
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+    /// Directory holding the archive_<n>.json shards.
+    #[arg(long, global = true, default_value = "archive")]
+    pub archive_dir: PathBuf,
+    /// Index of every gallery in the shards.
+    #[arg(long, global = true, default_value = "item_index.bin")]
+    pub index: PathBuf,
+    /// Galleries per shard when appending.
+    #[arg(long, global = true, default_value_t = MAX_ITEMS, value_parser = clap::value_parser!(u32).range(1..).map(|v| v as usize))]
+    pub max_items: usize,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Append galleries to the last shard, starting new shards as they fill up.
+    /// Galleries already in the index are skipped.
+    Append {
+        /// JSON files holding a gallery or an array of them, or directories of
+        /// such files like detail/.
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Put galleries in place of the archived ones with the same gid, rewriting
+    /// each affected shard once. Galleries not in the archive are appended.
+    Replace {
+        /// JSON files holding a gallery or an array of them, or directories of
+        /// such files like detail/.
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Rebuild the index from the shards, with the byte span of every gallery.
+    Index,
+    /// Print galleries as JSON lines, looked up through the index.
+    Get {
+        #[arg(required = true)]
+        gids: Vec<u64>,
+    },
+}
@@ -0,0 +1,456 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, metadata, read},
+    io::{self, BufReader, BufWriter, ErrorKind, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{commit, shard, temporary};
+
+/// Where one gallery is stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub gid: u64,
+    /// Position of the shard name in [`Index::files`].
+    pub file: u16,
+    /// Byte offset of the gallery's JSON object in the shard.
+    pub offset: u64,
+    /// Length of the object in bytes.
+    pub size: u64,
+}
+
+/// The contents of item_index.bin, see item_index_format.md.
+#[derive(Debug, Default, PartialEq)]
+pub struct Index {
+    /// Shard file names, without the directory.
+    pub files: Vec<String>,
+    pub entries: Vec<Entry>,
+}
+
+impl Index {
+    /// Reads the index at `path`, `None` if there is none yet.
+    pub fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        let bytes = match read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if bytes.starts_with(b"version https://git-lfs") {
+            anyhow::bail!("{} is a git-lfs pointer", path.display());
+        }
+        Self::parse(&bytes)
+            .map(Some)
+            .with_context(|| format!("reading {}", path.display()))
+    }
+
+    /// Indexes every shard in `dir`, entries sorted by gid. Objects without a
+    /// numeric gid are skipped with a warning.
+    pub fn build(dir: &Path) -> anyhow::Result<Self> {
+        let mut index = Self::default();
+        for (number, path) in shard::list(dir)? {
+            let file = index.file_id(&shard::name(number));
+            index.entries.extend(Self::scan(&path, file)?);
+        }
+        index.entries.sort_by_key(|v| v.gid);
+        Ok(index)
+    }
+
+    /// The entries of every gallery in the shard at `path`, which is `file`
+    /// in the index, in shard order.
+    pub fn scan(path: &Path, file: u16) -> anyhow::Result<Vec<Entry>> {
+        #[derive(Deserialize)]
+        struct Gid {
+            gid: Option<u64>,
+        }
+
+        let mut entries = vec![];
+        let reader = BufReader::with_capacity(1 << 20, File::open(path)?);
+        for span in shard::spans(reader) {
+            let (offset, bytes) = span.with_context(|| format!("indexing {}", path.display()))?;
+            let gid = serde_json::from_slice::<Gid>(&bytes)
+                .with_context(|| format!("indexing {} at byte {offset}", path.display()))?
+                .gid;
+            match gid {
+                Some(gid) => entries.push(Entry {
+                    gid,
+                    file,
+                    offset,
+                    size: bytes.len() as u64,
+                }),
+                None => eprintln!("skipping gallery without a gid at byte {offset}"),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Indexes anew every shard in `dir` that no longer matches this index,
+    /// loaded from `path`: one changed after the index was saved, or whose
+    /// galleries don't end where its entries do. That is what a run leaves
+    /// behind when it dies between renaming a shard into place and saving the
+    /// index. Entries of shards that are gone are dropped, and the index is
+    /// saved again if anything changed.
+    pub fn reconcile(&mut self, dir: &Path, path: &Path) -> anyhow::Result<()> {
+        let saved = metadata(path).and_then(|v| v.modified()).ok();
+        let shards = shard::list(dir)?;
+        let names = shards
+            .iter()
+            .map(|v| shard::name(v.0))
+            .collect::<HashSet<_>>();
+        let count = self.entries.len();
+        self.entries
+            .retain(|v| names.contains(&self.files[usize::from(v.file)]));
+        let mut changed = self.entries.len() != count;
+
+        let mut ends = HashMap::<u16, u64>::new();
+        for entry in &self.entries {
+            let end = ends.entry(entry.file).or_default();
+            *end = (*end).max(entry.offset + entry.size);
+        }
+        for (number, shard_path) in shards {
+            let file = self.file_id(&shard::name(number));
+            let mut shard_file = File::open(&shard_path)?;
+            let newer = match saved {
+                Some(saved) => shard_file.metadata()?.modified()? > saved,
+                None => false,
+            };
+            let end = shard::body_end(&mut shard_file)
+                .with_context(|| format!("reading {}", shard_path.display()))?;
+            // An empty shard ends right after its `[`.
+            if !newer && ends.get(&file).copied().unwrap_or(1) == end {
+                continue;
+            }
+            // Saving even an unchanged index keeps the shard from looking newer.
+            changed = true;
+            let entries = Self::scan(&shard_path, file)?;
+            let mut indexed = self
+                .entries
+                .iter()
+                .filter(|v| v.file == file)
+                .copied()
+                .collect::<Vec<_>>();
+            indexed.sort_by_key(|v| v.offset);
+            if indexed != entries {
+                eprintln!(
+                    "{} changed after the index was saved, indexing it again",
+                    shard_path.display()
+                );
+                self.entries.retain(|v| v.file != file);
+                self.entries.extend(entries);
+            }
+        }
+        if changed {
+            self.entries.sort_by_key(|v| v.gid);
+            self.save(path)?;
+        }
+        Ok(())
+    }
+
+    /// Reads either format. v1 entries are sorted on the way in.
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        match bytes.strip_prefix(MAGIC) {
+            Some(rest) => Self::parse_v2(rest),
+            None => Self::parse_v1(bytes),
+        }
+    }
+
+    fn parse_v1(bytes: &[u8]) -> io::Result<Self> {
+        let mut reader = Reader(bytes);
+        let files = reader.files()?;
+        if !reader.0.len().is_multiple_of(ENTRY_LEN) {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let mut entries = reader.entries(reader.0.len() / ENTRY_LEN)?;
+        entries.sort_by_key(|v| v.gid);
+        Self::checked(files, entries)
+    }
+
+    fn parse_v2(bytes: &[u8]) -> io::Result<Self> {
+        let (body, crc) = bytes
+            .split_last_chunk::<4>()
+            .ok_or(ErrorKind::UnexpectedEof)?;
+        let expected = u32::from_le_bytes(*crc);
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(MAGIC);
+        hasher.update(body);
+        if hasher.finalize() != expected {
+            return Err(invalid("checksum mismatch"));
+        }
+        let mut reader = Reader(body);
+        let version = u16::from_le_bytes(reader.array()?);
+        if version != 2 {
+            return Err(invalid(format!("unsupported version {version}")));
+        }
+        let files = reader.files()?;
+        let count = u64::from_le_bytes(reader.array()?);
+        if Some(reader.0.len() as u64) != count.checked_mul(ENTRY_LEN as u64) {
+            return Err(invalid(format!(
+                "{count} entries announced, {} bytes of them found",
+                reader.0.len()
+            )));
+        }
+        let entries = reader.entries(count as usize)?;
+        if !entries.is_sorted_by_key(|v| v.gid) {
+            return Err(invalid("entries not sorted by gid"));
+        }
+        Self::checked(files, entries)
+    }
+
+    /// Rejects entries pointing past the file list.
+    fn checked(files: Vec<String>, entries: Vec<Entry>) -> io::Result<Self> {
+        if entries.iter().any(|v| usize::from(v.file) >= files.len()) {
+            return Err(invalid("entry of an unknown shard"));
+        }
+        Ok(Self { files, entries })
+    }
+
+    /// Writes the index as v2. Entries have to be sorted by gid.
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        if !self.entries.is_sorted_by_key(|v| v.gid) {
+            return Err(invalid("entries not sorted by gid"));
+        }
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend((self.files.len() as u32).to_le_bytes());
+        for name in &self.files {
+            bytes.extend((name.len() as u16).to_le_bytes());
+            bytes.extend(name.as_bytes());
+        }
+        bytes.extend((self.entries.len() as u64).to_le_bytes());
+        for entry in &self.entries {
+            bytes.extend(entry.gid.to_le_bytes());
+            bytes.extend(entry.file.to_le_bytes());
+            bytes.extend(entry.offset.to_le_bytes());
+            bytes.extend(entry.size.to_le_bytes());
+        }
+        bytes.extend(crc32fast::hash(&bytes).to_le_bytes());
+        out.write_all(&bytes)
+    }
+
+    /// Writes the index to a temporary file and renames it over `path`.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp = temporary(path);
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        self.write(&mut out)?;
+        commit(out.into_inner()?, &tmp, path)
+    }
+
+    /// The id of the shard `name`, added to the file list if it is new.
+    pub fn file_id(&mut self, name: &str) -> u16 {
+        match self.files.iter().position(|v| v == name) {
+            Some(id) => id as u16,
+            None => {
+                self.files.push(name.to_owned());
+                (self.files.len() - 1) as u16
+            }
+        }
+    }
+
+    pub fn gids(&self) -> HashSet<u64> {
+        self.entries.iter().map(|v| v.gid).collect()
+    }
+
+    /// Where `gid` is stored, the first copy if the archive holds it twice.
+    pub fn get(&self, gid: u64) -> Option<&Entry> {
+        let i = self.entries.partition_point(|v| v.gid < gid);
+        self.entries.get(i).filter(|v| v.gid == gid)
+    }
+}
+
+const MAGIC: &[u8; 4] = b"EHIX";
+const VERSION: u16 = 2;
+/// gid: u64, file_index: u16, begin_offset: u64, size: u64
+const ENTRY_LEN: usize = 26;
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.into())
+}
+
+/// The unread rest of an index.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        let (head, tail) = self.0.split_at_checked(n).ok_or(ErrorKind::UnexpectedEof)?;
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn files(&mut self) -> io::Result<Vec<String>> {
+        let count = u32::from_le_bytes(self.array()?);
+        let mut files = vec![];
+        for _ in 0..count {
+            let len = u16::from_le_bytes(self.array()?);
+            let name = std::str::from_utf8(self.take(len.into())?)
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            files.push(name.to_owned());
+        }
+        Ok(files)
+    }
+
+    fn entries(&mut self, count: usize) -> io::Result<Vec<Entry>> {
+        Ok(self
+            .take(count * ENTRY_LEN)?
+            .chunks_exact(ENTRY_LEN)
+            .map(|v| Entry {
+                gid: u64::from_le_bytes(v[..8].try_into().unwrap()),
+                file: u16::from_le_bytes(v[8..10].try_into().unwrap()),
+                offset: u64::from_le_bytes(v[10..18].try_into().unwrap()),
+                size: u64::from_le_bytes(v[18..26].try_into().unwrap()),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs, process};
+
+    use super::{Entry, Index, MAGIC};
+
+    fn sample() -> Index {
+        let mut index = Index::default();
+        let file = index.file_id("archive_0.json");
+        assert_eq!(index.file_id("archive_1.json"), 1);
+        assert_eq!(index.file_id("archive_0.json"), file);
+        for (gid, file) in [(7, 0), (3731743, 1), (3731744, 1)] {
+            index.entries.push(Entry {
+                gid,
+                file,
+                offset: gid * 10,
+                size: 1234,
+            });
+        }
+        index
+    }
+
+    #[test]
+    fn round_trips() {
+        let index = sample();
+        let mut bytes = vec![];
+        index.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 4 + 2 + 4 + 2 * (2 + 14) + 8 + 3 * 26 + 4);
+        assert_eq!(Index::parse(&bytes).unwrap(), index);
+        assert_eq!(index.get(3731743).unwrap().offset, 37317430);
+        assert_eq!(index.get(8), None);
+
+        // Anything but the whole file is rejected.
+        for len in 0..bytes.len() {
+            assert!(Index::parse(&bytes[..len]).is_err(), "{len} bytes");
+        }
+        let mut unsorted = index;
+        unsorted.entries.swap(0, 1);
+        assert!(unsorted.write(&mut vec![]).is_err());
+    }
+
+    #[test]
+    fn reads_v1() {
+        let mut bytes = vec![];
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend(14u16.to_le_bytes());
+        bytes.extend(b"archive_0.json");
+        for gid in [3u64, 1, 2] {
+            bytes.extend(gid.to_le_bytes());
+            bytes.extend([0; 18]);
+        }
+        let index = Index::parse(&bytes).unwrap();
+        assert_eq!(index.files, vec!["archive_0.json"]);
+        assert_eq!(
+            index.entries.iter().map(|v| v.gid).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert!(Index::parse(&bytes[..bytes.len() - 1]).is_err());
+        // An entry of a shard that isn't listed.
+        bytes[28] = 1;
+        assert!(Index::parse(&bytes).is_err());
+    }
+
+    /// Random corruptions of a valid index either fail to parse or parse into
+    /// an index whose entries all point at a listed shard, never panic.
+    #[test]
+    fn fuzz_parse() {
+        let mut bytes = vec![];
+        sample().write(&mut bytes).unwrap();
+        let mut v1 = bytes[6..bytes.len() - 4].to_vec();
+        v1.drain(36..44);
+        assert_eq!(Index::parse(&v1).unwrap(), sample());
+        let mut rng = fastrand::Rng::with_seed(0x1d3);
+        for _ in 0..20_000 {
+            let mut input = if rng.bool() {
+                bytes.clone()
+            } else {
+                v1.clone()
+            };
+            match rng.u8(0..4) {
+                0 => input.truncate(rng.usize(0..=input.len())),
+                1 => {
+                    for _ in 0..rng.usize(1..4) {
+                        let i = rng.usize(0..input.len());
+                        input[i] = rng.u8(..);
+                    }
+                }
+                2 => {
+                    let i = rng.usize(0..=input.len());
+                    let extra = (0..rng.usize(1..40))
+                        .map(|_| rng.u8(..))
+                        .collect::<Vec<_>>();
+                    input.splice(i..i, extra);
+                }
+                _ => input = (0..rng.usize(0..200)).map(|_| rng.u8(..)).collect(),
+            }
+            // Most damage is caught by the checksum, so fix it up half the
+            // time to reach the checks behind it.
+            if input.starts_with(MAGIC) && input.len() >= 8 && rng.bool() {
+                let end = input.len() - 4;
+                let crc = crc32fast::hash(&input[..end]);
+                input[end..].copy_from_slice(&crc.to_le_bytes());
+            }
+            if let Ok(index) = Index::parse(&input) {
+                assert!(
+                    index
+                        .entries
+                        .iter()
+                        .all(|v| usize::from(v.file) < index.files.len())
+                );
+                assert!(index.entries.is_sorted_by_key(|v| v.gid));
+            }
+        }
+    }
+
+    #[test]
+    fn builds_from_shards() {
+        let dir = temp_dir().join(format!("ehdump-index-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("archive_0.json"),
+            "[\n{\"gid\":5,\"title\":\"{\"},\n{\"title\":\"no gid\"},\n{\"gid\":2}\n]",
+        )
+        .unwrap();
+        fs::write(dir.join("archive_1.json"), "[\n{\"gid\":3}\n]\n").unwrap();
+        fs::write(dir.join("archive_1.json.tmp"), "[").unwrap();
+
+        let entry = |gid, file, offset, size| Entry {
+            gid,
+            file,
+            offset,
+            size,
+        };
+        assert_eq!(
+            Index::build(&dir).unwrap(),
+            Index {
+                files: vec!["archive_0.json".to_owned(), "archive_1.json".to_owned()],
+                entries: vec![entry(2, 0, 45, 9), entry(3, 1, 2, 9), entry(5, 0, 2, 21)],
+            }
+        );
+
+        fs::write(dir.join("archive_1.json"), "[\n{\"gid\":3}").unwrap();
+        assert!(Index::build(&dir).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
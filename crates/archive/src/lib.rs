@@ -0,0 +1,39 @@
+//! The archive: galleries in `archive/archive_<n>.json` shards, each a JSON
+//! array of at most a fixed number of galleries, and `item_index.bin`
+//! pointing at every gallery in them.
+
+pub mod append;
+pub mod index;
+pub mod reader;
+pub mod replace;
+pub mod shard;
+
+pub use reader::ArchiveReader;
+
+use std::{
+    ffi::OsString,
+    fs::{File, rename},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// `path` with `.tmp` appended, where files are written before they are
+/// renamed into place.
+pub(crate) fn temporary(path: &Path) -> PathBuf {
+    let mut tmp = OsString::from(path.as_os_str());
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+/// Syncs the finished temporary file and renames it over `path`, so readers
+/// only ever see the old file or the complete new one.
+pub(crate) fn commit(file: File, tmp: &Path, path: &Path) -> io::Result<()> {
+    file.sync_all()?;
+    drop(file);
+    rename(tmp, path)?;
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|v| !v.as_os_str().is_empty()) {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,83 @@
+mod cli;
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, Write},
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use clap::Parser;
+use ehdump_archive::{ArchiveReader, append::Appender, index::Index, replace::replace, shard};
+use ehdump_model::layout;
+use serde_json::Value;
+
+use crate::cli::{Cli, Command};
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Append { inputs } => {
+            let mut appender = Appender::open(&cli.archive_dir, &cli.index, cli.max_items)?;
+            for_each_input(&inputs, |v| appender.push(v))?;
+            let (appended, skipped) = (appender.appended, appender.skipped);
+            appender.finish()?;
+            println!("appended {appended} galleries, skipped {skipped} already archived");
+        }
+        Command::Replace { inputs } => {
+            let mut galleries = HashMap::new();
+            for_each_input(&inputs, |v| {
+                let gid = v["gid"]
+                    .as_u64()
+                    .with_context(|| format!("gallery without a gid: {v}"))?;
+                galleries.insert(gid, v);
+                Ok(())
+            })?;
+            let replaced = replace(&cli.archive_dir, &cli.index, galleries, cli.max_items)?;
+            println!(
+                "replaced {} galleries in {} shards, appended {}",
+                replaced.replaced, replaced.shards, replaced.appended
+            );
+        }
+        Command::Index => {
+            let index = Index::build(&cli.archive_dir)?;
+            index.save(&cli.index)?;
+            println!(
+                "indexed {} galleries in {} shards",
+                index.entries.len(),
+                index.files.len()
+            );
+        }
+        Command::Get { gids } => {
+            let reader = ArchiveReader::open(&cli.archive_dir, &cli.index)?;
+            let mut out = io::stdout().lock();
+            for gallery in reader.get_many::<Value>(&gids)? {
+                writeln!(out, "{}", shard::canonical(&gallery))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Calls `f` with every gallery in `inputs`, files holding a gallery or an
+/// array of them and directories of such files, those in gid order.
+fn for_each_input(
+    inputs: &[PathBuf],
+    mut f: impl FnMut(Value) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    for input in inputs {
+        let paths = if input.is_dir() {
+            let mut files = layout::files(input, "json")?;
+            files.sort_unstable();
+            files.into_iter().map(|v| v.1).collect()
+        } else {
+            vec![input.clone()]
+        };
+        for path in paths {
+            shard::for_each_item(BufReader::new(File::open(&path)?), &mut f)
+                .with_context(|| format!("reading {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,250 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, de::DeserializeOwned};
+
+use crate::{
+    index::{Entry, Index},
+    shard,
+};
+
+/// Looks galleries up in the archive through the byte spans in the index,
+/// reading only the gallery asked for.
+///
+/// Galleries come back as any type they deserialize into, usually
+/// [`ehdump_model::Root1`] or `serde_json::Value`.
+pub struct ArchiveReader {
+    dir: PathBuf,
+    index: Index,
+}
+
+impl ArchiveReader {
+    /// Opens the archive in `dir` with the index at `index_path`.
+    pub fn open(dir: &Path, index_path: &Path) -> anyhow::Result<Self> {
+        let index = Index::load(index_path)?
+            .with_context(|| format!("{} not found", index_path.display()))?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            index,
+        })
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// The gallery `gid`, `None` if the archive doesn't have it.
+    pub fn get<T: DeserializeOwned>(&self, gid: u64) -> anyhow::Result<Option<T>> {
+        let Some(entry) = self.index.get(gid) else {
+            return Ok(None);
+        };
+        let mut file = File::open(self.path(entry))?;
+        self.read(&mut file, entry).map(Some)
+    }
+
+    /// The galleries among `gids` that the archive has, in archive order. Each
+    /// shard is opened once and read front to back.
+    pub fn get_many<T: DeserializeOwned>(&self, gids: &[u64]) -> anyhow::Result<Vec<T>> {
+        let mut entries = gids
+            .iter()
+            .filter_map(|&gid| self.index.get(gid))
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|v| (v.file, v.offset));
+        entries.dedup();
+        let mut galleries = vec![];
+        let mut open: Option<(u16, File)> = None;
+        for entry in entries {
+            let file = match &mut open {
+                Some((id, file)) if *id == entry.file => file,
+                _ => &mut open.insert((entry.file, File::open(self.path(entry))?)).1,
+            };
+            galleries.push(self.read(file, entry)?);
+        }
+        Ok(galleries)
+    }
+
+    /// Every gallery in every shard, see [`stream`].
+    pub fn iter<T: DeserializeOwned>(
+        &self,
+    ) -> anyhow::Result<impl Iterator<Item = anyhow::Result<T>>> {
+        stream(&self.dir)
+    }
+
+    fn path(&self, entry: &Entry) -> PathBuf {
+        self.dir.join(&self.index.files[usize::from(entry.file)])
+    }
+
+    fn read<T: DeserializeOwned>(&self, file: &mut File, entry: &Entry) -> anyhow::Result<T> {
+        if entry.size == 0 {
+            anyhow::bail!("the index has no byte spans, rebuild it with `archive index`");
+        }
+        #[derive(Deserialize)]
+        struct Gid {
+            gid: Option<u64>,
+        }
+
+        let mut bytes = vec![0; entry.size as usize];
+        file.seek(SeekFrom::Start(entry.offset))?;
+        file.read_exact(&mut bytes)?;
+        let context = || {
+            format!(
+                "reading gallery {} at byte {} of {}",
+                entry.gid,
+                entry.offset,
+                self.index.files[usize::from(entry.file)]
+            )
+        };
+        // A shard rewritten without the index saved after it moves galleries.
+        let gid = serde_json::from_slice::<Gid>(&bytes)
+            .with_context(context)?
+            .gid;
+        if gid != Some(entry.gid) {
+            anyhow::bail!(
+                "{} found gallery {gid:?} instead, the index is out of date",
+                context()
+            );
+        }
+        serde_json::from_slice(&bytes).with_context(context)
+    }
+}
+
+/// Every gallery in the shards in `dir`, in archive order, holding one at a
+/// time in memory. Needs no index.
+pub fn stream<T: DeserializeOwned>(
+    dir: &Path,
+) -> anyhow::Result<impl Iterator<Item = anyhow::Result<T>>> {
+    Ok(shard::list(dir)?.into_iter().flat_map(|(_, path)| {
+        let (spans, error) = match File::open(&path) {
+            Ok(file) => (Some(shard::spans(BufReader::new(file))), None),
+            Err(e) => (None, Some(Err(e.into()))),
+        };
+        spans
+            .into_iter()
+            .flatten()
+            .map(move |span| {
+                let (offset, bytes) = span?;
+                serde_json::from_slice(&bytes)
+                    .with_context(|| format!("reading {} at byte {offset}", path.display()))
+            })
+            .chain(error)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env::temp_dir,
+        fs, process,
+        time::{Duration, SystemTime},
+    };
+
+    use ehdump_model::Root1;
+    use serde_json::{Value, json};
+
+    use super::{ArchiveReader, stream};
+    use crate::append::Appender;
+
+    const DETAIL: &str = include_str!("../fixtures/gallery.json");
+
+    #[test]
+    fn reads_galleries() {
+        let dir = temp_dir().join(format!("ehdump-reader-{}", process::id()));
+        let archive = dir.join("archive");
+        let index_path = dir.join("item_index.bin");
+        let detail: Value = serde_json::from_str(DETAIL).unwrap();
+        let mut appender = Appender::open(&archive, &index_path, 2).unwrap();
+        for gid in [5, 3, 9, 1] {
+            let mut gallery = detail.clone();
+            gallery["gid"] = gid.into();
+            appender.push(gallery).unwrap();
+        }
+        appender.finish().unwrap();
+
+        let reader = ArchiveReader::open(&archive, &index_path).unwrap();
+        let gallery = reader.get::<Root1>(9).unwrap().unwrap();
+        assert_eq!(gallery.gid, 9);
+        assert_eq!(gallery.token, "67c3b3ec34");
+        assert!(reader.get::<Root1>(4).unwrap().is_none());
+
+        let gids = |galleries: Vec<Root1>| galleries.iter().map(|v| v.gid).collect::<Vec<_>>();
+        assert_eq!(
+            gids(reader.get_many(&[1, 9, 4, 5, 5]).unwrap()),
+            vec![5, 9, 1]
+        );
+        let streamed = stream(&archive).unwrap().collect::<anyhow::Result<_>>();
+        assert_eq!(gids(streamed.unwrap()), vec![5, 3, 9, 1]);
+
+        fs::write(archive.join("archive_1.json"), "[\n{\"gid\":9}").unwrap();
+        assert!(reader.iter::<Value>().unwrap().any(|v| v.is_err()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_moved_galleries() {
+        let dir = temp_dir().join(format!("ehdump-reader-moved-{}", process::id()));
+        let archive = dir.join("archive");
+        let index_path = dir.join("item_index.bin");
+        let mut appender = Appender::open(&archive, &index_path, 2).unwrap();
+        for gid in [1, 2] {
+            appender.push(json!({ "gid": gid })).unwrap();
+        }
+        appender.finish().unwrap();
+        // Same length, so only the gids give it away.
+        fs::write(
+            archive.join("archive_0.json"),
+            "[\n{\"gid\":2},\n{\"gid\":1}\n]\n",
+        )
+        .unwrap();
+        // Coarse timestamps could leave it looking as old as the index.
+        fs::File::options()
+            .write(true)
+            .open(archive.join("archive_0.json"))
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+
+        let reader = ArchiveReader::open(&archive, &index_path).unwrap();
+        let error = format!("{:#}", reader.get::<Value>(1).unwrap_err());
+        assert!(error.contains("out of date"), "{error}");
+        drop(reader);
+        // Opening the archive for writing indexes the shard again.
+        Appender::open(&archive, &index_path, 2).unwrap();
+        let reader = ArchiveReader::open(&archive, &index_path).unwrap();
+        assert_eq!(reader.get::<Value>(1).unwrap().unwrap()["gid"], 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_wrongly_typed_fields() {
+        let dir = temp_dir().join(format!("ehdump-reader-types-{}", process::id()));
+        let archive = dir.join("archive");
+        let index_path = dir.join("item_index.bin");
+        let detail: Value = serde_json::from_str(DETAIL).unwrap();
+        let mut appender = Appender::open(&archive, &index_path, 2).unwrap();
+        for (gid, field, value) in [
+            (1, "filecount", Value::Null),
+            (2, "parent_gid", true.into()),
+            (3, "torrentcount", Value::Array(vec![])),
+        ] {
+            let mut gallery = detail.clone();
+            gallery["gid"] = gid.into();
+            gallery[field] = value;
+            appender.push(gallery).unwrap();
+        }
+        appender.finish().unwrap();
+
+        let reader = ArchiveReader::open(&archive, &index_path).unwrap();
+        for gid in [1, 2, 3] {
+            let Err(error) = reader.get::<Root1>(gid) else {
+                panic!("gallery {gid} deserialized");
+            };
+            let error = format!("{error:#}");
+            assert!(error.contains("invalid type"), "{error}");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
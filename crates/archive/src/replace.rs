@@ -0,0 +1,222 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    append::Appender,
+    commit,
+    index::{Entry, Index},
+    shard, temporary,
+};
+
+/// What [`replace`] did.
+#[derive(Debug, Default, PartialEq)]
+pub struct Replaced {
+    pub replaced: usize,
+    /// Galleries the archive didn't have, appended instead.
+    pub appended: usize,
+    pub shards: usize,
+}
+
+/// Puts each gallery in `galleries` in place of the archived one with its gid.
+///
+/// Every shard holding one of them is streamed into a temporary file once,
+/// with the new galleries swapped in, and renamed over the old shard. Its
+/// index entries are then rebuilt from the new offsets and the index saved.
+/// A shard renamed into place without the index saved after it is indexed
+/// anew the next time the archive is opened for writing, and readers refuse
+/// offsets that no longer lead to the gallery asked for. Galleries the
+/// archive doesn't have are appended.
+pub fn replace(
+    dir: &Path,
+    index_path: &Path,
+    mut galleries: HashMap<u64, Value>,
+    max_items: usize,
+) -> anyhow::Result<Replaced> {
+    let mut replaced = Replaced::default();
+    if let Some(mut index) = Index::load(index_path)? {
+        index.reconcile(dir, index_path)?;
+        let mut files = index
+            .entries
+            .iter()
+            .filter(|v| galleries.contains_key(&v.gid))
+            .map(|v| v.file)
+            .collect::<Vec<_>>();
+        files.sort_unstable();
+        files.dedup();
+        for file in files {
+            replaced.replaced += rewrite(dir, &mut index, file, &mut galleries)?;
+            replaced.shards += 1;
+            index.save(index_path)?;
+        }
+    }
+    if !galleries.is_empty() {
+        let mut galleries = galleries.into_values().collect::<Vec<_>>();
+        galleries.sort_by_key(|v| v["gid"].as_u64());
+        let mut appender = Appender::open(dir, index_path, max_items)?;
+        for gallery in galleries {
+            appender.push(gallery)?;
+        }
+        replaced.appended = appender.appended;
+        appender.finish()?;
+    }
+    Ok(replaced)
+}
+
+/// Rewrites shard `file` with the galleries it holds taken out of
+/// `galleries`, and replaces its index entries. Returns how many it swapped.
+fn rewrite(
+    dir: &Path,
+    index: &mut Index,
+    file: u16,
+    galleries: &mut HashMap<u64, Value>,
+) -> anyhow::Result<usize> {
+    #[derive(Deserialize)]
+    struct Gid {
+        gid: Option<u64>,
+    }
+
+    let path = dir.join(&index.files[usize::from(file)]);
+    let tmp = temporary(&path);
+    let reader = BufReader::with_capacity(1 << 20, File::open(&path)?);
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    out.write_all(b"[")?;
+    let mut offset = 1;
+    let mut entries = vec![];
+    let (mut count, mut swapped) = (0, 0);
+    for span in shard::spans(reader) {
+        let (old_offset, bytes) = span.with_context(|| format!("reading {}", path.display()))?;
+        let gid = serde_json::from_slice::<Gid>(&bytes)
+            .with_context(|| format!("reading {} at byte {old_offset}", path.display()))?
+            .gid;
+        let separator: &[u8] = if count > 0 { b",\n" } else { b"\n" };
+        out.write_all(separator)?;
+        offset += separator.len() as u64;
+        // A gallery archived twice is replaced in both places.
+        let new = gid.and_then(|gid| galleries.get(&gid));
+        let bytes = match new {
+            Some(gallery) => {
+                swapped += 1;
+                shard::canonical(gallery).into_bytes()
+            }
+            None => bytes,
+        };
+        out.write_all(&bytes)?;
+        if let Some(gid) = gid {
+            entries.push(Entry {
+                gid,
+                file,
+                offset,
+                size: bytes.len() as u64,
+            });
+        }
+        offset += bytes.len() as u64;
+        count += 1;
+    }
+    out.write_all(b"\n]\n")?;
+    commit(out.into_inner()?, &tmp, &path)?;
+
+    for entry in &entries {
+        galleries.remove(&entry.gid);
+    }
+    index.entries.retain(|v| v.file != file);
+    index.entries.extend(entries);
+    index.entries.sort_by_key(|v| v.gid);
+    Ok(swapped)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, env::temp_dir, fs, process};
+
+    use serde_json::{Value, json};
+
+    use super::{Replaced, replace};
+    use crate::{append::Appender, index::Index, reader::ArchiveReader};
+
+    #[test]
+    fn replaces_in_place() {
+        let dir = temp_dir().join(format!("ehdump-replace-{}", process::id()));
+        let archive = dir.join("archive");
+        let index_path = dir.join("item_index.bin");
+        let item = |gid: u64, title: &str| json!({ "gid": gid, "title": title });
+        let mut appender = Appender::open(&archive, &index_path, 2).unwrap();
+        for gid in [1, 2, 3, 4] {
+            appender.push(item(gid, "old")).unwrap();
+        }
+        appender.finish().unwrap();
+        let untouched = fs::read(archive.join("archive_1.json")).unwrap();
+
+        let galleries = [item(2, "a much longer new title"), item(7, "new")]
+            .into_iter()
+            .map(|v| (v["gid"].as_u64().unwrap(), v))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(
+            replace(&archive, &index_path, galleries, 2).unwrap(),
+            Replaced {
+                replaced: 1,
+                appended: 1,
+                shards: 1,
+            }
+        );
+
+        assert_eq!(fs::read(archive.join("archive_1.json")).unwrap(), untouched);
+        assert_eq!(
+            fs::read_to_string(archive.join("archive_0.json")).unwrap(),
+            "[\n{\"gid\":1,\"title\":\"old\"},\n{\"gid\":2,\"title\":\"a much longer new title\"}\n]\n"
+        );
+        assert!(!archive.join("archive_0.json.tmp").exists());
+        // The rebuilt entries match a fresh index.
+        assert_eq!(
+            Index::load(&index_path).unwrap().unwrap(),
+            Index::build(&archive).unwrap()
+        );
+        let reader = ArchiveReader::open(&archive, &index_path).unwrap();
+        let titles = reader
+            .get_many::<Value>(&[1, 2, 3, 7])
+            .unwrap()
+            .into_iter()
+            .map(|v| v["title"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, vec!["old", "a much longer new title", "old", "new"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn recovers_unsaved_index() {
+        let dir = temp_dir().join(format!("ehdump-replace-crash-{}", process::id()));
+        let archive = dir.join("archive");
+        let index_path = dir.join("item_index.bin");
+        let item = |gid: u64, title: &str| json!({ "gid": gid, "title": title });
+        let mut appender = Appender::open(&archive, &index_path, 4).unwrap();
+        for gid in [1, 2, 3] {
+            appender.push(item(gid, "old")).unwrap();
+        }
+        appender.finish().unwrap();
+        let saved = fs::read(&index_path).unwrap();
+        let galleries = HashMap::from([(1, item(1, "longer"))]);
+        replace(&archive, &index_path, galleries, 4).unwrap();
+        // As if the run died after the shard was renamed into place but
+        // before the index was saved.
+        fs::write(&index_path, saved).unwrap();
+        let reader = ArchiveReader::open(&archive, &index_path).unwrap();
+        assert!(reader.get::<Value>(2).is_err());
+
+        let galleries = HashMap::from([(3, item(3, "new"))]);
+        replace(&archive, &index_path, galleries, 4).unwrap();
+        assert_eq!(
+            Index::load(&index_path).unwrap().unwrap(),
+            Index::build(&archive).unwrap()
+        );
+        let reader = ArchiveReader::open(&archive, &index_path).unwrap();
+        assert_eq!(reader.get::<Value>(2).unwrap().unwrap()["title"], "old");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    fmt,
+    fs::{File, read_dir},
+    io::{self, BufRead, ErrorKind, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use serde::{
+    Deserialize, Deserializer,
+    de::{self, IgnoredAny, MapAccess, SeqAccess, Visitor, value::MapAccessDeserializer},
+};
+use serde_json::Value;
+
+/// Galleries per shard before a new one is started.
+pub const MAX_ITEMS: usize = 40_000;
+
+pub fn name(number: u32) -> String {
+    format!("archive_{number}.json")
+}
+
+/// The number of an `archive_<n>.json` shard.
+pub fn number(name: &str) -> Option<u32> {
+    name.strip_prefix("archive_")?
+        .strip_suffix(".json")?
+        .parse()
+        .ok()
+}
+
+/// The shards in `dir` by number, none if it doesn't exist.
+pub fn list(dir: &Path) -> io::Result<Vec<(u32, PathBuf)>> {
+    let files = match read_dir(dir) {
+        Ok(files) => files,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut shards = vec![];
+    for file in files {
+        let path = file?.path();
+        if let Some(number) = path.file_name().and_then(|v| v.to_str()).and_then(number) {
+            shards.push((number, path));
+        }
+    }
+    shards.sort_unstable();
+    Ok(shards)
+}
+
+/// A gallery as it is stored: compact, keys sorted and non-ASCII text kept as
+/// is, like Python's `json.dumps(sort_keys=True, ensure_ascii=False)`.
+pub fn canonical(item: &Value) -> String {
+    // serde_json's map is a BTreeMap, so keys come out sorted.
+    serde_json::to_string(item).unwrap()
+}
+
+/// Calls `f` with each element of a JSON array, or once with a lone object,
+/// without holding more than one in memory.
+pub fn for_each_item(
+    reader: impl Read,
+    f: impl FnMut(Value) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut each = Each { f, error: None };
+    let result = serde_json::Deserializer::from_reader(reader).deserialize_any(&mut each);
+    match each.error {
+        Some(e) => Err(e),
+        None => Ok(result?),
+    }
+}
+
+/// How many galleries the shard in `reader` holds.
+pub fn count(reader: impl Read) -> serde_json::Result<usize> {
+    Ok(Vec::<IgnoredAny>::deserialize(&mut serde_json::Deserializer::from_reader(reader))?.len())
+}
+
+/// Where the content of a shard ends: before the whitespace that precedes
+/// its closing `]`.
+pub fn body_end(file: &mut File) -> io::Result<u64> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let start = len.saturating_sub(4096);
+    file.seek(SeekFrom::Start(start))?;
+    let mut tail = vec![];
+    file.read_to_end(&mut tail)?;
+    let tail = tail.trim_ascii_end();
+    let Some(body) = tail.strip_suffix(b"]") else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "shard doesn't end with ]",
+        ));
+    };
+    Ok(start + body.trim_ascii_end().len() as u64)
+}
+
+/// The byte offset and the bytes of each object in the shard in `reader`,
+/// found without parsing them.
+pub fn spans<R: BufRead>(reader: R) -> Spans<R> {
+    Spans {
+        reader,
+        offset: 0,
+        scanner: Scanner::default(),
+        done: false,
+    }
+}
+
+pub struct Spans<R> {
+    reader: R,
+    offset: u64,
+    scanner: Scanner,
+    done: bool,
+}
+
+impl<R: BufRead> Spans<R> {
+    fn advance(&mut self) -> anyhow::Result<Option<(u64, Vec<u8>)>> {
+        loop {
+            let chunk = self.reader.fill_buf()?;
+            if chunk.is_empty() {
+                if !self.scanner.closed {
+                    anyhow::bail!("cut off at byte {}", self.offset);
+                }
+                return Ok(None);
+            }
+            let mut used = 0;
+            let mut found = None;
+            for &byte in chunk {
+                used += 1;
+                let done = self.scanner.step(byte, self.offset)?;
+                self.offset += 1;
+                if done {
+                    found = Some((self.scanner.start, std::mem::take(&mut self.scanner.object)));
+                    break;
+                }
+            }
+            self.reader.consume(used);
+            if found.is_some() {
+                return Ok(found);
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Spans<R> {
+    type Item = anyhow::Result<(u64, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.advance().transpose();
+        self.done = !matches!(next, Some(Ok(_)));
+        next
+    }
+}
+
+/// Where in the array a byte is.
+#[derive(Default)]
+struct Scanner {
+    /// 0 before the array, 1 between its elements, more inside an object.
+    depth: usize,
+    closed: bool,
+    in_string: bool,
+    escaped: bool,
+    start: u64,
+    object: Vec<u8>,
+}
+
+impl Scanner {
+    /// Takes the byte at `offset`, true once it completes an object.
+    fn step(&mut self, byte: u8, offset: u64) -> anyhow::Result<bool> {
+        match self.depth {
+            _ if byte.is_ascii_whitespace() && self.depth < 2 => {}
+            0 if byte == b'[' && !self.closed => self.depth = 1,
+            1 if byte == b',' => {}
+            1 if byte == b']' => {
+                self.depth = 0;
+                self.closed = true;
+            }
+            1 if byte == b'{' => {
+                self.depth = 2;
+                self.start = offset;
+                self.object.push(byte);
+            }
+            0 | 1 => anyhow::bail!("unexpected {:?} at byte {offset}", byte as char),
+            _ => {
+                self.object.push(byte);
+                if self.in_string {
+                    match byte {
+                        _ if self.escaped => self.escaped = false,
+                        b'\\' => self.escaped = true,
+                        b'"' => self.in_string = false,
+                        _ => {}
+                    }
+                } else {
+                    match byte {
+                        b'"' => self.in_string = true,
+                        b'{' | b'[' => self.depth += 1,
+                        b'}' | b']' => self.depth -= 1,
+                        _ => {}
+                    }
+                    return Ok(self.depth == 1);
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+struct Each<F> {
+    f: F,
+    /// The error `f` stopped with, kept whole instead of as a message.
+    error: Option<anyhow::Error>,
+}
+
+impl<F: FnMut(Value) -> anyhow::Result<()>> Each<F> {
+    fn call<E: de::Error>(&mut self, item: Value) -> Result<(), E> {
+        (self.f)(item).map_err(|e| {
+            self.error = Some(e);
+            E::custom("stopped")
+        })
+    }
+}
+
+impl<'de, F: FnMut(Value) -> anyhow::Result<()>> Visitor<'de> for &mut Each<F> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a gallery or an array of galleries")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(item) = seq.next_element()? {
+            self.call(item)?;
+        }
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<(), A::Error> {
+        let item = Value::deserialize(MapAccessDeserializer::new(map))?;
+        self.call(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::{canonical, count, for_each_item, number, spans};
+
+    #[test]
+    fn reads_items() {
+        let mut items = vec![];
+        for text in [r#"[{"gid":1},{"gid":2}]"#, r#"{"gid":3}"#] {
+            for_each_item(text.as_bytes(), |v| {
+                items.push(v["gid"].as_u64().unwrap());
+                Ok(())
+            })
+            .unwrap();
+        }
+        assert_eq!(items, vec![1, 2, 3]);
+        assert!(for_each_item(&b"[{\"gid\":1},"[..], |_| Ok(())).is_err());
+        let error = for_each_item(&b"[1,2]"[..], |_| anyhow::bail!("no")).unwrap_err();
+        assert_eq!(error.to_string(), "no");
+
+        assert_eq!(count(&b"[\n{\"a\":[1]},\n{}\n]\n"[..]).unwrap(), 2);
+        assert_eq!(number("archive_12.json"), Some(12));
+        assert_eq!(number("archive_12.json.tmp"), None);
+    }
+
+    #[test]
+    fn finds_spans() {
+        let shard = "[\n{\"gid\":1,\"t\":\"}]\\\"{\"},\n{\"gid\":2,\"a\":[{}]}\n]\n";
+        let found = spans(shard.as_bytes())
+            .map(|v| {
+                let (offset, bytes) = v.unwrap();
+                let item: Value = serde_json::from_slice(&bytes).unwrap();
+                (offset, bytes.len(), item["gid"].as_u64().unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(found, vec![(2, 21, 1), (25, 18, 2)]);
+        assert_eq!(&shard[2..23], r#"{"gid":1,"t":"}]\"{"}"#);
+
+        for broken in ["[\n{\"gid\":1}", "[1]", "[{}] []", "{}"] {
+            assert!(spans(broken.as_bytes()).any(|v| v.is_err()), "{broken}");
+        }
+    }
+
+    #[test]
+    fn writes_like_python() {
+        let item: Value = json!({
+            "title": "ü \"x\"\n",
+            "gid": 1,
+            "tags": ["b", "a"],
+            "torrents": [{"name": "n", "added": "1"}],
+        });
+        assert_eq!(
+            canonical(&item),
+            r#"{"gid":1,"tags":["b","a"],"title":"ü \"x\"\n","torrents":[{"added":"1","name":"n"}]}"#
+        );
+    }
+}
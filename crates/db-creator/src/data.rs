@@ -1,5 +1,7 @@
 use std::ops::Range;
 
+use ehdump_model::Category;
+
 use crate::arena::StrRef;
 
 pub struct Torrent {
@@ -19,7 +21,7 @@ pub struct Item {
     pub title: StrRef,
     pub title_jpn: Option<StrRef>,
     pub thumb: StrRef,
-    pub category: u8,
+    pub category: Category,
     pub rating: f64,
 
     pub tags: Range<usize>,
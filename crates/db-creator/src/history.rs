@@ -0,0 +1,115 @@
+use std::{fs::read_to_string, io, path::Path};
+
+use ahash::AHashMap;
+use ehdump_model::{Change, Root1, layout};
+use serde_json::{Map, Value};
+
+use crate::{Db, data::Item};
+
+/// Every recorded change per gid, oldest first.
+#[derive(Default)]
+pub struct History {
+    changes: AHashMap<u64, Vec<Change>>,
+}
+
+impl History {
+    /// Reads the `.jsonl` files in `dir`. A missing directory is an empty history.
+    pub fn load(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let mut changes = AHashMap::new();
+        for (gid, file) in layout::files(dir.as_ref(), "jsonl")? {
+            let mut lines = read_to_string(&file)?
+                .lines()
+                .filter(|v| !v.trim().is_empty())
+                .map(serde_json::from_str::<Change>)
+                .collect::<Result<Vec<_>, _>>()?;
+            lines.sort_by_key(|v| v.dumped);
+            changes.insert(gid, lines);
+        }
+        Ok(Self { changes })
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn get(&self, gid: u64) -> Option<&[Change]> {
+        self.changes.get(&gid).map(Vec::as_slice)
+    }
+}
+
+/// The detail fields as of `time`, or `None` if `time` is before the first
+/// recorded version.
+pub fn replay(changes: &[Change], time: u64) -> Option<Map<String, Value>> {
+    let mut detail = Map::new();
+    let mut dumped = None;
+    for change in changes.iter().take_while(|v| v.dumped <= time) {
+        for key in &change.unset {
+            detail.remove(key);
+        }
+        detail.extend(change.set.iter().map(|(k, v)| (k.clone(), v.clone())));
+        dumped = Some(change.dumped);
+    }
+    detail.insert("dumped".to_owned(), Value::from(dumped?));
+    Some(detail)
+}
+
+/// A gallery as it looked at some point in time.
+pub enum Snapshot<'a> {
+    /// Nothing changed since, this is the gallery as built into the `Db`.
+    Current(&'a Item),
+    /// An older version rebuilt from the history.
+    Past(Box<Root1>),
+}
+
+impl Db {
+    /// What `gid` looked like at unix time `time`, or `None` if it isn't
+    /// known or `time` is before it was first dumped.
+    pub fn at(&self, gid: u64, time: u64) -> serde_json::Result<Option<Snapshot<'_>>> {
+        let Some(item) = self.items.get(&gid) else {
+            return Ok(None);
+        };
+        match self.history.get(gid) {
+            Some(changes) if changes.last().is_some_and(|v| v.dumped > time) => {
+                let Some(detail) = replay(changes, time) else {
+                    return Ok(None);
+                };
+                Ok(Some(Snapshot::Past(serde_json::from_value(detail.into())?)))
+            }
+            None if time < item.dumped => Ok(None),
+            _ => Ok(Some(Snapshot::Current(item))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ehdump_model::Change;
+    use serde_json::json;
+
+    use super::replay;
+
+    #[test]
+    fn replays_changes() {
+        let changes = [
+            json!({ "dumped": 10, "set": { "title": "a", "rating": "4.5", "error": "e" } }),
+            json!({ "dumped": 20, "set": { "rating": "4.6" }, "unset": ["error"] }),
+            json!({ "dumped": 30, "set": { "title": "b" } }),
+        ]
+        .map(|v| serde_json::from_value::<Change>(v).unwrap());
+
+        assert_eq!(replay(&changes, 5), None);
+        assert_eq!(
+            replay(&changes, 10).unwrap(),
+            *json!({ "dumped": 10, "title": "a", "rating": "4.5", "error": "e" })
+                .as_object()
+                .unwrap()
+        );
+        assert_eq!(
+            replay(&changes, 25).unwrap(),
+            *json!({ "dumped": 20, "title": "a", "rating": "4.6" })
+                .as_object()
+                .unwrap()
+        );
+        assert_eq!(replay(&changes, 30).unwrap()["title"], "b");
+    }
+}
@@ -1,6 +1,9 @@
+// Nothing queries the Db yet, it is only built and measured.
+#[allow(dead_code)]
 mod arena;
+#[allow(dead_code)]
 mod data;
-mod parser;
+mod history;
 
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
@@ -9,25 +12,48 @@ use std::{
     collections::HashMap,
     fs::{File, read_to_string},
     hash::Hash,
+    path::Path,
     thread::sleep,
     time::Duration,
 };
 
 use ahash::AHashMap;
+use ehdump_model::{Root1, TagPrefix, layout};
 
 use crate::{
     arena::{Arena, StrRef, StringArena},
     data::{Item, Tag, Torrent},
-    parser::Root1,
+    history::{History, Snapshot},
 };
 
 fn main() {
-    let db = build();
+    let (db, warnings) = build();
     println!("done");
+    warnings.log();
     log_db_memory(&db);
+    // `db-creator <gid> <unix time>` shows what a gallery looked like back then.
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let [gid, time] = args.as_slice() {
+        print_at(&db, gid.parse().unwrap(), time.parse().unwrap());
+        return;
+    }
     sleep(Duration::from_hours(1));
 }
 
+fn print_at(db: &Db, gid: u64, time: u64) {
+    match db.at(gid, time).unwrap() {
+        Some(Snapshot::Current(item)) => println!(
+            "{gid} at {time}: {:?} as last dumped at {}",
+            db.arena.get(item.title),
+            item.dumped
+        ),
+        Some(Snapshot::Past(file)) => {
+            println!("{gid} at {time}: {}", serde_json::to_string(&file).unwrap())
+        }
+        None => println!("{gid} at {time}: not dumped yet"),
+    }
+}
+
 pub struct HashSetIdBuilder<T: Hash + Eq + PartialEq> {
     data: HashMap<T, usize>,
     counter: usize,
@@ -64,7 +90,35 @@ impl<T: Hash + Eq + PartialEq> HashSetIdBuilder<T> {
     }
 }
 
-fn build() -> Db {
+/// Recoverable oddities seen while building, reported with the build summary.
+#[derive(Default)]
+pub struct Warnings {
+    unknown_namespaces: HashMap<String, usize>,
+}
+
+impl Warnings {
+    fn check(&mut self, file: &Root1) {
+        for tag in &file.tags {
+            if let TagPrefix::Unknown(namespace) = &tag.prefix {
+                *self
+                    .unknown_namespaces
+                    .entry(namespace.clone())
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    fn log(&self) {
+        let total: usize = self.unknown_namespaces.values().sum();
+        println!("Warnings:\n- tags with unknown namespaces: {total}");
+        for (namespace, count) in &self.unknown_namespaces {
+            println!("  - {namespace}: {count}");
+        }
+    }
+}
+
+fn build() -> (Db, Warnings) {
+    let mut warnings = Warnings::default();
     let mut users = HashSetIdBuilder::default();
     let mut tags = HashSetIdBuilder::default();
     let mut arena = StringArena::new();
@@ -77,25 +131,25 @@ fn build() -> Db {
         .filter_map(|v| v.split_once(":"))
         .map(|v| (v.0.parse::<u64>().unwrap(), v.1.to_owned()))
         .collect::<HashMap<_, _>>();
-    for file in std::fs::read_dir("archive").unwrap() {
-        let file = file.unwrap().path();
-        let file: Vec<Root1> = simd_json::serde::from_reader(File::open(&file).unwrap()).unwrap();
-        for file in file {
-            let item = transform(
-                file,
-                &mut users,
-                &mut tags,
-                &disowned,
-                &mut arena,
-                &mut t_arena,
-                &mut to_arena,
-            );
-            items.insert(item.gid, item);
-        }
+    // One gallery at a time, a whole shard would be parsed into memory at once.
+    for file in ehdump_archive::reader::stream::<Root1>(Path::new("archive")).unwrap() {
+        let file = file.unwrap();
+        warnings.check(&file);
+        let item = transform(
+            file,
+            &mut users,
+            &mut tags,
+            &disowned,
+            &mut arena,
+            &mut t_arena,
+            &mut to_arena,
+        );
+        items.insert(item.gid, item);
     }
-    for file in std::fs::read_dir("detail").unwrap() {
-        let file = file.unwrap().path();
+    // Sharded or flat, temporary files left by an interrupted downloader are skipped.
+    for (_, file) in layout::files(Path::new("detail"), "json").unwrap() {
         let file: Root1 = serde_json::from_reader(File::open(file).unwrap()).unwrap();
+        warnings.check(&file);
         let item = transform(
             file,
             &mut users,
@@ -126,6 +180,7 @@ fn build() -> Db {
             .collect::<Vec<_>>()
             .into_boxed_slice(),
         items,
+        history: History::load("history").unwrap(),
         arena,
         t_arena,
         to_arena,
@@ -133,7 +188,7 @@ fn build() -> Db {
     db.arena.finalize();
     db.to_arena.finalize();
     db.t_arena.finalize();
-    db
+    (db, warnings)
 }
 
 fn log_db_memory(db: &Db) {
@@ -153,7 +208,8 @@ fn log_db_memory(db: &Db) {
         - tags: {} entries, heap ~{}\n\
         - arena: ~{} bytes\n\
         - torrent_arena: ~{} bytes\n\
-        - tag_arena: ~{} bytes",
+        - tag_arena: ~{} bytes\n\
+        - history: {} galleries",
         db.items.len(),
         items_stack,
         items_heap,
@@ -164,6 +220,7 @@ fn log_db_memory(db: &Db) {
         arena_heap,
         to_arena_heap,
         t_arena_heap,
+        db.history.len(),
     );
 }
 
@@ -183,6 +240,7 @@ pub struct Db {
     to_arena: Arena<Torrent>,
     t_arena: Arena<Tag>,
     items: AHashMap<u64, Item>,
+    history: History,
 }
 
 fn transform(
@@ -203,9 +261,15 @@ fn transform(
     let uid = uid.map(|v| users.insert(v));
     let mut _tags = vec![];
     for tag in file.tags {
+        let category = tag.prefix.id();
+        // Unknown namespaces share one category, so keep the namespace in the name.
+        let name = match tag.prefix {
+            TagPrefix::Unknown(namespace) => format!("{namespace}:{}", tag.tag),
+            _ => tag.tag,
+        };
         _tags.push(data::Tag {
-            id: tags.insert(tag.tag),
-            category: tag.prefix as u8,
+            id: tags.insert(name),
+            category,
         });
     }
     Item {
@@ -216,7 +280,7 @@ fn transform(
         title: arena.add(&file.title),
         title_jpn: file.title_jpn.map(|v| arena.add(&v)),
         thumb: arena.add(&file.thumb.replace("https://ehgt.org/", "")),
-        category: file.category as u8,
+        category: file.category,
         rating: file.rating,
         tags: tag_arena.add_slice(_tags),
         filecount: file.filecount,
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::client::RateLimitedClient;
+
+/// Result of a gdata request, keyed by the gid each entry reports for itself.
+/// Entries the API answered with `{"gid":..,"error":".."}` hold the error message.
+pub type Gdata = HashMap<u64, Result<Value, String>>;
+
+pub async fn api(
+    client: &RateLimitedClient,
+    url: &str,
+    ids: Vec<(u64, String)>,
+) -> anyhow::Result<Gdata> {
+    let payload = json!({
+      "method": "gdata",
+      "gidlist": ids,
+      "namespace": 1
+    });
+    let body = client.text(|c| c.post(url).json(&payload)).await?;
+    let data: Data = serde_json::from_str(&body)?;
+    Ok(by_gid(data.gmetadata))
+}
+
+/// Resolves `(gid, page_token, page)` triples to gallery tokens through the
+/// gtoken method, keyed by gid.
+pub async fn gtoken(
+    client: &RateLimitedClient,
+    url: &str,
+    pages: Vec<(u64, String, u32)>,
+) -> anyhow::Result<HashMap<u64, Result<String, String>>> {
+    let payload = json!({
+      "method": "gtoken",
+      "pagelist": pages,
+    });
+    let body = client.text(|c| c.post(url).json(&payload)).await?;
+    let data: TokenList = serde_json::from_str(&body)?;
+    Ok(by_gid(data.tokenlist)
+        .into_iter()
+        .map(|(gid, entry)| {
+            let token = entry.and_then(|v| match v.get("token").and_then(Value::as_str) {
+                Some(token) => Ok(token.to_owned()),
+                None => Err("gtoken entry without token".to_owned()),
+            });
+            (gid, token)
+        })
+        .collect())
+}
+
+/// Keys API entries by their own `gid`, turning `{"gid":..,"error":".."}` into errors.
+fn by_gid(entries: Vec<Value>) -> HashMap<u64, Result<Value, String>> {
+    let mut map = HashMap::with_capacity(entries.len());
+    for entry in entries {
+        let Some(gid) = entry.get("gid").and_then(Value::as_u64) else {
+            eprintln!("api entry without gid: {entry}");
+            continue;
+        };
+        let entry = match entry.get("error") {
+            Some(error) => Err(error.as_str().unwrap_or("unknown error").to_owned()),
+            None => Ok(entry),
+        };
+        map.insert(gid, entry);
+    }
+    map
+}
+
+#[derive(Deserialize)]
+struct Data {
+    gmetadata: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+struct TokenList {
+    tokenlist: Vec<Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Client;
+    use serde_json::json;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{body_partial_json, method},
+    };
+
+    use super::{api, gtoken};
+    use crate::client::{RateLimitedClient, RetryPolicy};
+
+    fn client() -> RateLimitedClient {
+        RateLimitedClient::new(Client::new(), 0.0, RetryPolicy::default())
+    }
+
+    #[tokio::test]
+    async fn gdata_is_keyed_by_gid() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "gdata" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "gmetadata": [
+                    { "gid": 2, "error": "Key missing, or incorrect key provided." },
+                    { "gid": 1, "token": "aaaaaaaaaa", "title": "one" },
+                ]
+            })))
+            .mount(&server)
+            .await;
+
+        let ids = vec![
+            (1, "aaaaaaaaaa".to_owned()),
+            (2, "bbbbbbbbbb".to_owned()),
+            (3, "cccccccccc".to_owned()),
+        ];
+        let gdata = api(&client(), &server.uri(), ids).await.unwrap();
+        assert_eq!(gdata[&1].as_ref().unwrap()["title"], "one");
+        assert!(gdata[&2].is_err());
+        assert!(!gdata.contains_key(&3));
+    }
+
+    #[tokio::test]
+    async fn gtoken_resolves_pages() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({
+                "method": "gtoken",
+                "pagelist": [[3731743, "0123456789", 12]],
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "tokenlist": [{ "gid": 3731743, "token": "67c3b3ec34" }]
+            })))
+            .mount(&server)
+            .await;
+
+        let pages = vec![(3731743, "0123456789".to_owned(), 12)];
+        let tokens = gtoken(&client(), &server.uri(), pages).await.unwrap();
+        assert_eq!(tokens[&3731743], Ok("67c3b3ec34".to_owned()));
+    }
+}
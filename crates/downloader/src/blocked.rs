@@ -0,0 +1,194 @@
+use std::{
+    fmt,
+    fs::read_to_string,
+    io::{self, ErrorKind},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A response that is a notice about us instead of the page asked for. The
+/// site sends these with status 200, so they have to be recognised by text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Blocked {
+    /// The IP is banned, for as long as the page says if it says.
+    Banned(Option<Duration>),
+    /// Only logged in users may see the page, or exhentai's empty page.
+    LoginRequired,
+    /// Warned for loading pages too fast, a ban follows if we carry on.
+    RateLimited,
+    Maintenance,
+}
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Blocked::Banned(Some(duration)) => {
+                write!(f, "ip banned for another {}s", duration.as_secs())
+            }
+            Blocked::Banned(None) => write!(f, "ip banned"),
+            Blocked::LoginRequired => write!(f, "login required"),
+            Blocked::RateLimited => write!(f, "rate limited"),
+            Blocked::Maintenance => write!(f, "site down for maintenance"),
+        }
+    }
+}
+
+impl std::error::Error for Blocked {}
+
+/// Notices are short plain pages, real responses are larger or structured.
+const MAX_NOTICE_LEN: usize = 4096;
+
+impl Blocked {
+    /// Recognises the notice in a successful response, if it is one.
+    pub fn classify(body: &str) -> Option<Self> {
+        let body = body.trim();
+        if body.is_empty() {
+            return Some(Blocked::LoginRequired);
+        }
+        if body.len() > MAX_NOTICE_LEN || body.starts_with(['{', '[']) || body.starts_with("<?xml")
+        {
+            return None;
+        }
+        if body.contains("has been temporarily banned") {
+            let expires = body
+                .split_once("ban expires in ")
+                .and_then(|v| parse_duration(v.1));
+            Some(Blocked::Banned(expires))
+        } else if body.contains("requires you to log on") {
+            Some(Blocked::LoginRequired)
+        } else if body.contains("opening pages too fast") {
+            Some(Blocked::RateLimited)
+        } else if body.to_ascii_lowercase().contains("maintenance") {
+            Some(Blocked::Maintenance)
+        } else {
+            None
+        }
+    }
+
+    /// How long to leave the site alone before trying again.
+    pub fn back_off(&self) -> Duration {
+        match self {
+            Blocked::Banned(Some(duration)) => *duration + Duration::from_secs(60),
+            Blocked::Banned(None) | Blocked::LoginRequired => Duration::from_secs(60 * 60),
+            Blocked::RateLimited => Duration::from_secs(5 * 60),
+            Blocked::Maintenance => Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+/// Until when the site is left alone, kept on disk so the next cron run
+/// doesn't knock on a ban either.
+#[derive(Serialize, Deserialize)]
+pub struct BackOff {
+    /// Unix time.
+    pub until: u64,
+    pub reason: String,
+}
+
+impl BackOff {
+    pub fn new(blocked: &Blocked) -> Self {
+        Self {
+            until: (now() + blocked.back_off()).as_secs(),
+            reason: blocked.to_string(),
+        }
+    }
+
+    /// Reads the back off file. A missing or malformed file is no back off.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Option<Self>> {
+        match read_to_string(path) {
+            Ok(text) => Ok(serde_json::from_str(&text).ok()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// How much longer to wait, `None` once the back off is over.
+    pub fn remaining(&self) -> Option<Duration> {
+        Duration::from_secs(self.until)
+            .checked_sub(now())
+            .filter(|v| !v.is_zero())
+    }
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Parses `2 days, 23 hours and 59 minutes` up to the end of the sentence.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.split('.').next()?;
+    let words = text
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|v| !v.is_empty() && *v != "and")
+        .collect::<Vec<_>>();
+    let mut secs = 0;
+    for pair in words.chunks(2) {
+        let [count, unit] = pair else {
+            return None;
+        };
+        let count = count.parse::<u64>().ok()?;
+        let unit = match unit.trim_end_matches('s') {
+            "day" => 24 * 60 * 60,
+            "hour" => 60 * 60,
+            "minute" => 60,
+            "second" => 1,
+            _ => return None,
+        };
+        secs += count * unit;
+    }
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Blocked;
+
+    #[test]
+    fn classifies_notices() {
+        let cases = [
+            (
+                "Your IP address has been temporarily banned for excessive pageloads which indicates that you are using automated mirroring/harvesting software. The ban expires in 23 hours and 59 minutes",
+                Some(Blocked::Banned(Some(Duration::from_secs(
+                    23 * 60 * 60 + 59 * 60,
+                )))),
+            ),
+            (
+                "Your IP address has been temporarily banned for excessive pageloads. The ban expires in 1 day, 2 hours, 3 minutes and 4 seconds.",
+                Some(Blocked::Banned(Some(Duration::from_secs(
+                    24 * 60 * 60 + 2 * 60 * 60 + 3 * 60 + 4,
+                )))),
+            ),
+            (
+                "Your IP address has been temporarily banned.",
+                Some(Blocked::Banned(None)),
+            ),
+            (
+                "<html><body><p>This page requires you to log on.</p></body></html>",
+                Some(Blocked::LoginRequired),
+            ),
+            ("", Some(Blocked::LoginRequired)),
+            (
+                "You are opening pages too fast, thus placing a heavy load on the server. Back down, or your IP address will be automatically banned.",
+                Some(Blocked::RateLimited),
+            ),
+            (
+                "E-Hentai Galleries is down for Maintenance.",
+                Some(Blocked::Maintenance),
+            ),
+            (r#"{"gmetadata":[{"title":"maintenance"}]}"#, None),
+            (
+                r#"<?xml version="1.0"?><feed><title>maintenance</title></feed>"#,
+                None,
+            ),
+        ];
+        for (body, expected) in cases {
+            assert_eq!(Blocked::classify(body), expected, "{body:?}");
+        }
+    }
+}
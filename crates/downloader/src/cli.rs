@@ -0,0 +1,109 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use crate::{REQUESTS_PER_SECOND, report::Kind, site::Site};
+
+/// Dumps e-hentai gallery metadata from the RSS feed and the gdata API.
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+    /// Directory holding data/, detail/ and the queue files.
+    #[arg(long, global = true, default_value = ".")]
+    pub out_dir: PathBuf,
+    /// Site to dump, sets the default endpoints below.
+    #[arg(long, global = true, value_enum, default_value_t = Site::EHentai)]
+    pub site: Site,
+    /// gdata API endpoint.
+    #[arg(long, global = true)]
+    pub api_url: Option<String>,
+    /// RSS feed to poll for new galleries.
+    #[arg(long, global = true)]
+    pub rss_url: Option<String>,
+    /// Front page listing to crawl for galleries the feed missed.
+    #[arg(long, global = true)]
+    pub site_url: Option<String>,
+    /// Member id cookie of the account used to log in, needed for exhentai.
+    #[arg(long, global = true, env = "IPB_MEMBER_ID")]
+    pub ipb_member_id: Option<String>,
+    /// Password hash cookie of the account used to log in, needed for exhentai.
+    #[arg(long, global = true, env = "IPB_PASS_HASH", hide_env_values = true)]
+    pub ipb_pass_hash: Option<String>,
+    /// Galleries per gdata request (the API accepts at most 25).
+    #[arg(long, global = true, default_value_t = 25, value_parser = clap::value_parser!(u16).range(1..=25))]
+    pub batch_size: u16,
+    /// gdata requests in flight at once, still within `--requests-per-second`.
+    #[arg(long, global = true, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    pub concurrency: u16,
+    /// Upper bound on requests started per second.
+    #[arg(long, global = true, default_value_t = REQUESTS_PER_SECOND)]
+    pub requests_per_second: f64,
+    /// Tries per request before a network error or error status is given up on.
+    #[arg(long, global = true, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_attempts: u32,
+    /// Milliseconds to wait before the first retry, doubling with each one.
+    #[arg(long, global = true, default_value_t = 500)]
+    pub base_delay: u64,
+    /// Most seconds to wait between retries, also the cap on a Retry-After.
+    #[arg(long, global = true, default_value_t = 60)]
+    pub max_delay: u64,
+    /// Fetch and report, but don't write any files.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Dump galleries new in the RSS feed, retry earlier failures and work through
+    /// requests.jsonl (the default).
+    Rss,
+    /// Dump specific galleries, one gallery URL, page URL or `gid/token` per line.
+    Fetch {
+        #[arg(long)]
+        gids: PathBuf,
+    },
+    /// Re-fetch galleries whose detail file is older than `--max-age`, newest galleries first.
+    Refresh {
+        /// Re-fetch galleries dumped more than this many days ago.
+        #[arg(long, default_value_t = 30)]
+        max_age: u64,
+        /// Most galleries to re-fetch in one run.
+        #[arg(long, default_value_t = 1000)]
+        budget: usize,
+    },
+    /// Fetch details for data files that have none.
+    Backfill,
+    /// Crawl the front page for galleries with gids between `--from` and `--to`,
+    /// or for every gap in gaps.jsonl.
+    Crawl {
+        #[arg(long, requires = "to")]
+        from: Option<u64>,
+        #[arg(long, requires = "from")]
+        to: Option<u64>,
+    },
+    /// Rebuild state.jsonl from data/ and detail/.
+    RebuildState,
+    /// Move the files of flat data/, detail/ and history/ directories into
+    /// `<gid / 1000>/` subdirectories.
+    MigrateLayout,
+    /// Print the gid ranges missing from archive/, detail/ and data/ as JSON lines.
+    Report {
+        /// Only print ranges of this kind.
+        #[arg(long, value_enum)]
+        kind: Option<Kind>,
+        /// Also count the galleries in this item_index.bin as held.
+        #[arg(long)]
+        index: Option<PathBuf>,
+    },
+    /// Keep running, doing an rss run every `--interval` seconds until SIGTERM or Ctrl-C.
+    Daemon {
+        /// Seconds between runs.
+        #[arg(long, default_value_t = 900)]
+        interval: u64,
+        /// Up to this many seconds are added to each wait at random.
+        #[arg(long, default_value_t = 60)]
+        jitter: u64,
+    },
+}
@@ -0,0 +1,319 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use reqwest::{Client, RequestBuilder, Response, StatusCode, header::RETRY_AFTER};
+use tokio::{
+    sync::Mutex,
+    time::{Instant, sleep, sleep_until},
+};
+
+use crate::blocked::Blocked;
+
+/// How often and how patiently a failed request is retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff for the given (zero based) retry, with the upper
+    /// half jittered so concurrent callers don't retry in lockstep.
+    fn backoff(&self, retry: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        delay.mul_f64(0.5 + fastrand::f64() * 0.5)
+    }
+}
+
+/// Spaces requests out so no more than `requests_per_second` start each second.
+struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_second: f64) -> Self {
+        let interval = if requests_per_second > 0.0 {
+            Duration::from_secs_f64(1.0 / requests_per_second)
+        } else {
+            Duration::ZERO
+        };
+        Self {
+            interval,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn acquire(&self) {
+        let at = {
+            let mut next = self.next.lock().await;
+            let at = (*next).max(Instant::now());
+            *next = at + self.interval;
+            at
+        };
+        sleep_until(at).await;
+    }
+}
+
+/// A `reqwest::Client` that rate limits and retries every request sent through it.
+pub struct RateLimitedClient {
+    client: Client,
+    limiter: RateLimiter,
+    policy: RetryPolicy,
+}
+
+impl RateLimitedClient {
+    pub fn new(client: Client, requests_per_second: f64, policy: RetryPolicy) -> Self {
+        Self {
+            client,
+            limiter: RateLimiter::new(requests_per_second),
+            policy,
+        }
+    }
+
+    /// Sends the request built by `build`, retrying timeouts, connection errors,
+    /// 429 and 5xx responses. A `Retry-After` is capped at the policy's
+    /// `max_delay`. `build` is called again for every attempt.
+    pub async fn send<F>(&self, build: F) -> anyhow::Result<Response>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.limiter.acquire().await;
+            let (error, retry_after) = match build(&self.client).send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => (
+                    anyhow::Error::new(Blocked::RateLimited).context(format!(
+                        "{} returned {}",
+                        response.url(),
+                        response.status()
+                    )),
+                    retry_after(&response),
+                ),
+                Ok(response) if response.status().is_server_error() => (
+                    anyhow!("{} returned {}", response.url(), response.status()),
+                    retry_after(&response),
+                ),
+                Ok(response) => return Err(response.error_for_status().unwrap_err().into()),
+                Err(e) if e.is_timeout() || e.is_connect() => (e.into(), None),
+                Err(e) => return Err(e.into()),
+            };
+            if attempt >= self.policy.max_attempts {
+                return Err(error.context(format!("giving up after {attempt} attempts")));
+            }
+            // The server's wait is honoured, but never beyond our own limit.
+            let delay = retry_after
+                .map(|v| v.min(self.policy.max_delay))
+                .unwrap_or_else(|| self.policy.backoff(attempt - 1));
+            eprintln!("{error:#}, retrying in {delay:?}");
+            sleep(delay).await;
+        }
+    }
+
+    /// Like [`send`](Self::send), but reads the body and turns ban, login and
+    /// maintenance notices into a [`Blocked`] error.
+    pub async fn text<F>(&self, build: F) -> anyhow::Result<String>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let response = self.send(build).await?;
+        let url = response.url().clone();
+        let body = response.text().await?;
+        match Blocked::classify(&body) {
+            Some(blocked) => Err(anyhow::Error::new(blocked).context(format!("requesting {url}"))),
+            None => Ok(body),
+        }
+    }
+}
+
+/// Parses `Retry-After` as either delay-seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value)
+        .ok()?
+        .with_timezone(&Utc);
+    (at - Utc::now()).to_std().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::Client;
+    use tokio::time::{Instant, timeout};
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, path},
+    };
+
+    use super::{RateLimitedClient, RetryPolicy};
+    use crate::blocked::Blocked;
+
+    fn client(requests_per_second: f64, max_attempts: u32) -> RateLimitedClient {
+        RateLimitedClient::new(
+            Client::new(),
+            requests_per_second,
+            RetryPolicy {
+                max_attempts,
+                base_delay: Duration::from_millis(10),
+                max_delay: Duration::from_millis(50),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api.php"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .mount(&server)
+            .await;
+
+        let url = format!("{}/api.php", server.uri());
+        let response = client(0.0, 5).send(|c| c.post(&url)).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(502))
+            .mount(&server)
+            .await;
+
+        let url = server.uri();
+        assert!(client(0.0, 3).send(|c| c.get(&url)).await.is_err());
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let url = server.uri();
+        assert!(client(0.0, 3).send(|c| c.get(&url)).await.is_err());
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn honours_retry_after() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let url = server.uri();
+        let client = RateLimitedClient::new(
+            Client::new(),
+            0.0,
+            RetryPolicy {
+                max_attempts: 2,
+                base_delay: Duration::from_millis(10),
+                max_delay: Duration::from_secs(10),
+            },
+        );
+        let start = Instant::now();
+        client.send(|c| c.get(&url)).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn caps_retry_after() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503).insert_header("Retry-After", "3600"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let url = server.uri();
+        // An hour long Retry-After is cut down to the 50ms max_delay.
+        timeout(
+            Duration::from_secs(10),
+            client(0.0, 2).send(|c| c.get(&url)),
+        )
+        .await
+        .expect("Retry-After was not capped")
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn limits_request_rate() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let url = server.uri();
+        let client = client(20.0, 1);
+        let start = Instant::now();
+        for _ in 0..5 {
+            client.send(|c| c.get(&url)).await.unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn recognises_bans() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "Your IP address has been temporarily banned for excessive pageloads. The ban expires in 59 minutes and 10 seconds",
+            ))
+            .mount(&server)
+            .await;
+
+        let url = server.uri();
+        let error = client(0.0, 3).text(|c| c.get(&url)).await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<Blocked>(),
+            Some(&Blocked::Banned(Some(Duration::from_secs(59 * 60 + 10))))
+        );
+        // A ban is not retried.
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashSet;
+
+use scraper::{Html, Selector};
+
+use crate::{client::RateLimitedClient, gallery_ref::GalleryRef};
+
+/// The galleries listed on one front page or search result page.
+#[derive(Debug, PartialEq)]
+pub struct Page {
+    /// `(gid, token)` in the order the page lists them, newest first.
+    pub galleries: Vec<(u64, String)>,
+    /// The `next=<gid>` cursor of the following page, `None` on the last one.
+    pub next: Option<u64>,
+}
+
+pub fn parse_page(html: &str) -> Page {
+    let html = Html::parse_document(html);
+    let link = Selector::parse("table.itg a[href]").unwrap();
+    let next = Selector::parse("a#unext[href], a#dnext[href]").unwrap();
+
+    let mut seen = HashSet::new();
+    let galleries = html
+        .select(&link)
+        .filter_map(|v| v.value().attr("href")?.parse::<GalleryRef>().ok())
+        .filter_map(|v| match v {
+            GalleryRef::Gallery { gid, token } => Some((gid, token)),
+            GalleryRef::Page { .. } => None,
+        })
+        .filter(|v| seen.insert(v.0))
+        .collect();
+    let next = html
+        .select(&next)
+        .filter_map(|v| v.value().attr("href"))
+        .find_map(|href| {
+            let query = href.split_once('?')?.1;
+            query
+                .split('&')
+                .find_map(|v| v.strip_prefix("next="))?
+                .parse()
+                .ok()
+        });
+    Page { galleries, next }
+}
+
+/// Walks the listing at `url` backwards from gid `to` and returns every
+/// gallery with a gid in `from..=to`. Stops once a page reaches below `from`.
+pub async fn crawl(
+    client: &RateLimitedClient,
+    url: &str,
+    from: u64,
+    to: u64,
+) -> anyhow::Result<Vec<(u64, String)>> {
+    let mut galleries = vec![];
+    // A page lists the galleries older than its cursor.
+    let mut cursor = to.saturating_add(1);
+    loop {
+        let body = client
+            .text(|c| c.get(url).query(&[("next", cursor)]))
+            .await?;
+        let page = parse_page(&body);
+        let lowest = page.galleries.iter().map(|v| v.0).min();
+        galleries.extend(
+            page.galleries
+                .into_iter()
+                .filter(|v| (from..=to).contains(&v.0)),
+        );
+        match (page.next, lowest) {
+            (Some(next), Some(lowest)) if lowest > from && next < cursor => cursor = next,
+            _ => break,
+        }
+    }
+    Ok(galleries)
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Client;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, query_param},
+    };
+
+    use super::{Page, crawl, parse_page};
+    use crate::client::{RateLimitedClient, RetryPolicy};
+
+    const FRONT_PAGE: &str = include_str!("../fixtures/front_page.html");
+    const LAST_PAGE: &str = include_str!("../fixtures/last_page.html");
+
+    fn gallery(gid: u64, token: &str) -> (u64, String) {
+        (gid, token.to_owned())
+    }
+
+    #[test]
+    fn parses_pages() {
+        assert_eq!(
+            parse_page(FRONT_PAGE),
+            Page {
+                galleries: vec![
+                    gallery(3731744, "0123456789"),
+                    gallery(3731743, "67c3b3ec34"),
+                    gallery(3731741, "abcdef0123"),
+                ],
+                next: Some(3731740),
+            }
+        );
+        assert_eq!(
+            parse_page(LAST_PAGE),
+            Page {
+                galleries: vec![
+                    gallery(3731738, "fedcba9876"),
+                    gallery(3731735, "1111111111"),
+                ],
+                next: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn crawls_to_target() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(query_param("next", "3731745"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(FRONT_PAGE))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(query_param("next", "3731740"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(LAST_PAGE))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = RateLimitedClient::new(Client::new(), 0.0, RetryPolicy::default());
+        let galleries = crawl(&client, &server.uri(), 3731736, 3731744)
+            .await
+            .unwrap();
+        assert_eq!(
+            galleries,
+            vec![
+                gallery(3731744, "0123456789"),
+                gallery(3731743, "67c3b3ec34"),
+                gallery(3731741, "abcdef0123"),
+                gallery(3731738, "fedcba9876"),
+            ]
+        );
+
+        // The first page already reaches the target.
+        let galleries = crawl(&client, &server.uri(), 3731743, 3731744)
+            .await
+            .unwrap();
+        assert_eq!(
+            galleries,
+            vec![
+                gallery(3731744, "0123456789"),
+                gallery(3731743, "67c3b3ec34"),
+            ]
+        );
+    }
+}
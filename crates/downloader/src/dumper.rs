@@ -0,0 +1,599 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fs::{File, OpenOptions, create_dir_all, read_to_string, remove_file, rename},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::{Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ehdump_model::layout;
+use futures_util::{StreamExt, stream};
+use serde_json::{Map, Value};
+
+use crate::{
+    api::{api, gtoken},
+    blocked::Blocked,
+    client::RateLimitedClient,
+    gallery_ref::GalleryRef,
+    history,
+    retry::Failure,
+    rss::Item,
+    site::Site,
+    state::State,
+};
+
+/// Writes `contents` to a synced temporary file next to `path` and renames it
+/// over `path`, creating parent directories, so a crash leaves either the old
+/// file or the new one.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    create_dir_all(parent)?;
+    let mut tmp = OsString::from(path.as_os_str());
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    rename(&tmp, path)?;
+    // The rename itself only survives a crash once the directory is synced.
+    #[cfg(unix)]
+    File::open(parent)?.sync_all()?;
+    Ok(())
+}
+
+/// What [`Dumper::fetch`] did.
+#[derive(Debug, Default)]
+pub struct Fetched {
+    /// Galleries a detail file was written for.
+    pub dumped: usize,
+    pub failed: Vec<Failure>,
+}
+
+/// Everything a run needs: the HTTP client, where to talk to and where to write.
+pub struct Dumper {
+    pub client: RateLimitedClient,
+    /// What is known about every gallery, kept up to date as files are written.
+    pub state: Mutex<State>,
+    pub out_dir: PathBuf,
+    pub site: Site,
+    pub api_url: String,
+    pub rss_url: String,
+    pub site_url: String,
+    pub batch_size: usize,
+    /// gdata requests in flight at once, all still held to the client's rate limit.
+    pub concurrency: usize,
+    pub dry_run: bool,
+}
+
+impl Dumper {
+    pub fn path(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.out_dir.join(name)
+    }
+
+    pub fn data_path(&self, gid: u64) -> PathBuf {
+        layout::path(&self.path("data"), gid, "json")
+    }
+
+    pub fn detail_path(&self, gid: u64) -> PathBuf {
+        layout::path(&self.path("detail"), gid, "json")
+    }
+
+    pub fn history_path(&self, gid: u64) -> PathBuf {
+        layout::path(&self.path("history"), gid, "jsonl")
+    }
+
+    /// Writes `contents` to `path` with [`write_atomically`]. A stale flat copy
+    /// of a sharded `path` is removed. Does nothing on a dry run.
+    pub fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        if self.dry_run {
+            println!("would write {}", path.display());
+            return Ok(());
+        }
+        write_atomically(path, contents)?;
+        match layout::flat(path) {
+            Some(flat) => self.remove(&flat),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes `path` if it exists. Does nothing on a dry run.
+    pub fn remove(&self, path: &Path) -> io::Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        match remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Appends `contents` to `path`, creating it and its parent directories. A
+    /// flat copy of a sharded `path` is moved into place first. Does nothing
+    /// on a dry run.
+    pub fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        if self.dry_run {
+            println!("would append to {}", path.display());
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        if let Some(flat) = layout::flat(path)
+            && !path.exists()
+            && flat.exists()
+        {
+            rename(flat, path)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(contents)
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Records how `detail` differs from the detail file it is about to
+    /// replace. The first change of a gallery also records the old version
+    /// in full.
+    fn record_history(&self, gid: u64, detail: &Map<String, Value>) -> anyhow::Result<()> {
+        let old = match read_to_string(layout::find(&self.path("detail"), gid, "json")) {
+            Ok(old) => old,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let old: Map<String, Value> = serde_json::from_str(&old)?;
+        let Some(change) = history::diff(&old, detail) else {
+            return Ok(());
+        };
+        let path = self.history_path(gid);
+        let mut lines = String::new();
+        if !path.exists() {
+            lines.push_str(&serde_json::to_string(&history::snapshot(&old))?);
+            lines.push('\n');
+        }
+        lines.push_str(&serde_json::to_string(&change)?);
+        lines.push('\n');
+        Ok(self.append(&path, lines.as_bytes())?)
+    }
+
+    pub fn write_data(&self, item: &Item) -> anyhow::Result<()> {
+        let t = serde_json::to_string(item)?;
+        self.write(&self.data_path(item.gid), t.as_bytes())?;
+        Ok(self
+            .state()
+            .update(item.gid, &item.token, |v| v.data = true)?)
+    }
+
+    /// Turns references into `(gid, token)` jobs, looking page URLs up through
+    /// gtoken. Page URLs that can't be resolved come back as failures holding
+    /// the page. Fails if the site is [`Blocked`], the remaining lookups would
+    /// be refused too.
+    pub async fn resolve(
+        &self,
+        refs: Vec<GalleryRef>,
+    ) -> anyhow::Result<(Vec<(u64, String)>, Vec<Failure>)> {
+        let mut jobs = vec![];
+        let mut failed = vec![];
+        let mut pages = vec![];
+        for gallery in refs {
+            match gallery {
+                GalleryRef::Gallery { gid, token } => jobs.push((gid, token)),
+                GalleryRef::Page {
+                    page_token,
+                    gid,
+                    page,
+                } => pages.push((gid, page_token, page)),
+            }
+        }
+        for pages in pages.chunks(self.batch_size.max(1)) {
+            let mut fail = |(gid, page_token, page): &(u64, String, u32), reason: String| {
+                eprintln!("failed to resolve {gid}: {reason}");
+                let page = GalleryRef::Page {
+                    page_token: page_token.clone(),
+                    gid: *gid,
+                    page: *page,
+                };
+                failed.push(Failure {
+                    gid: *gid,
+                    token: String::new(),
+                    reason,
+                    attempts: 1,
+                    page: Some(page.to_string()),
+                });
+            };
+            match gtoken(&self.client, &self.api_url, pages.to_vec()).await {
+                Ok(tokens) => {
+                    for page in pages {
+                        match tokens.get(&page.0) {
+                            Some(Ok(token)) => jobs.push((page.0, token.clone())),
+                            Some(Err(reason)) => fail(page, reason.clone()),
+                            None => fail(page, "missing from gtoken response".to_owned()),
+                        }
+                    }
+                }
+                Err(e) if e.is::<Blocked>() => return Err(e),
+                Err(e) => {
+                    for page in pages {
+                        fail(page, format!("gtoken request failed: {e:#}"));
+                    }
+                }
+            }
+        }
+        Ok((jobs, failed))
+    }
+
+    /// Looks `jobs` up through gdata in batches, up to `concurrency` at a
+    /// time, and writes a detail file for every gallery the API returned as
+    /// each batch comes back, plus a data file built from gdata for those
+    /// without one. Jobs are taken one batch at a time as requests finish, so
+    /// a long iterator is never held in memory. Returns what was dumped and
+    /// what failed, or an error once the site is [`Blocked`].
+    pub async fn fetch(
+        &self,
+        jobs: impl IntoIterator<Item = (u64, String)>,
+    ) -> anyhow::Result<Fetched> {
+        self.fetch_feed(jobs, &HashMap::new()).await
+    }
+
+    /// [`Dumper::fetch`], but galleries in `feed` get their data file written
+    /// from the feed item instead of from gdata.
+    pub async fn fetch_feed(
+        &self,
+        jobs: impl IntoIterator<Item = (u64, String)>,
+        feed: &HashMap<u64, &Item>,
+    ) -> anyhow::Result<Fetched> {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        // Batches in flight at once mustn't write the same gallery, and one
+        // listed again after its batch finished needn't be fetched twice.
+        let in_flight = Mutex::new(HashSet::new());
+        let batches = stream::iter(jobs)
+            .chunks(self.batch_size.max(1))
+            .map(|jobs| {
+                let jobs = {
+                    let state = self.state();
+                    let mut in_flight = in_flight.lock().unwrap();
+                    jobs.into_iter()
+                        .filter(|v| state.dumped(v.0).is_none_or(|t| t < started))
+                        .filter(|v| in_flight.insert(v.0))
+                        .collect::<Vec<_>>()
+                };
+                let in_flight = &in_flight;
+                async move {
+                    let fetched = self.fetch_batch(&jobs, feed).await;
+                    let mut in_flight = in_flight.lock().unwrap();
+                    for (gid, _) in &jobs {
+                        in_flight.remove(gid);
+                    }
+                    fetched
+                }
+            })
+            .buffer_unordered(self.concurrency.max(1));
+        let mut batches = pin!(batches);
+        let mut fetched = Fetched::default();
+        while let Some(batch) = batches.next().await {
+            let batch = batch?;
+            fetched.dumped += batch.dumped;
+            fetched.failed.extend(batch.failed);
+        }
+        Ok(fetched)
+    }
+
+    async fn fetch_batch(
+        &self,
+        jobs: &[(u64, String)],
+        feed: &HashMap<u64, &Item>,
+    ) -> anyhow::Result<Fetched> {
+        if jobs.is_empty() {
+            return Ok(Fetched::default());
+        }
+        let mut dumped = 0;
+        let mut failed = vec![];
+        let mut fail = |gid: u64, token: &str, reason: String| {
+            eprintln!("failed to fetch {gid}: {reason}");
+            failed.push(Failure {
+                gid,
+                token: token.to_owned(),
+                reason,
+                attempts: 1,
+                page: None,
+            });
+        };
+        let mut files = match api(&self.client, &self.api_url, jobs.to_vec()).await {
+            Ok(files) => files,
+            Err(e) if e.is::<Blocked>() => return Err(e),
+            Err(e) => {
+                for (gid, token) in jobs {
+                    fail(*gid, token, format!("gdata request failed: {e:#}"));
+                }
+                return self.record_failures(0, failed);
+            }
+        };
+        for (gid, token) in jobs {
+            let mut file = match files.remove(gid) {
+                Some(Ok(file)) => file,
+                Some(Err(reason)) => {
+                    fail(*gid, token, reason);
+                    continue;
+                }
+                None => {
+                    fail(*gid, token, "missing from gdata response".to_owned());
+                    continue;
+                }
+            };
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            if let Value::Object(map) = &mut file {
+                map.insert("dumped".to_string(), Value::from(now));
+                self.record_history(*gid, map)?;
+            }
+            self.write(
+                &self.detail_path(*gid),
+                serde_json::to_string(&file)?.as_bytes(),
+            )?;
+            self.state().update(*gid, token, |v| {
+                v.dumped = Some(now);
+                v.errors = 0;
+                v.error = None;
+            })?;
+            dumped += 1;
+            if let Some(item) = feed.get(gid) {
+                self.write_data(item)?;
+            } else if !self.state().has_data(*gid) {
+                match Item::from_gdata(&file, self.site) {
+                    Ok(item) => self.write_data(&item)?,
+                    Err(e) => eprintln!("no data file for {gid}: {e}"),
+                }
+            }
+        }
+        self.record_failures(dumped, failed)
+    }
+
+    fn record_failures(&self, dumped: usize, failed: Vec<Failure>) -> anyhow::Result<Fetched> {
+        let mut state = self.state();
+        for failure in &failed {
+            state.update(failure.gid, &failure.token, |v| {
+                v.errors += 1;
+                v.error = Some(failure.reason.clone());
+            })?;
+        }
+        Ok(Fetched { dumped, failed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env::temp_dir,
+        fs::read_dir,
+        process,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use ehdump_model::layout;
+    use reqwest::Client;
+    use serde_json::{Map, Value, json};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate, matchers::method};
+
+    use super::Dumper;
+    use crate::{
+        client::{RateLimitedClient, RetryPolicy},
+        gallery_ref::GalleryRef,
+        site::Site,
+        state::State,
+    };
+
+    fn dumper(name: &str, api_url: String) -> Dumper {
+        let out_dir = temp_dir().join(format!("ehdump-{name}-{}", process::id()));
+        std::fs::create_dir_all(&out_dir).unwrap();
+        Dumper {
+            client: RateLimitedClient::new(Client::new(), 0.0, RetryPolicy::default()),
+            state: Mutex::new(State::open(out_dir.join("state.jsonl"), false).unwrap()),
+            out_dir,
+            site: Site::EHentai,
+            api_url,
+            rss_url: String::new(),
+            site_url: String::new(),
+            batch_size: 25,
+            concurrency: 1,
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn write_replaces_files() {
+        let dumper = dumper("write", String::new());
+        let path = dumper.detail_path(1);
+        dumper.write(&path, b"old").unwrap();
+        dumper.write(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        // Nothing but the file itself is left in the directory.
+        assert_eq!(read_dir(path.parent().unwrap()).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dumper.out_dir).unwrap();
+    }
+
+    #[test]
+    fn replaces_flat_files() {
+        let dumper = dumper("flat", String::new());
+        let flat = dumper.path("detail").join("1.json");
+        std::fs::create_dir_all(flat.parent().unwrap()).unwrap();
+        std::fs::write(&flat, r#"{"gid":1,"title":"old"}"#).unwrap();
+        let mut detail = Map::new();
+        detail.insert("gid".to_owned(), json!(1));
+        detail.insert("title".to_owned(), json!("new"));
+        dumper.record_history(1, &detail).unwrap();
+        dumper
+            .write(
+                &dumper.detail_path(1),
+                &serde_json::to_vec(&detail).unwrap(),
+            )
+            .unwrap();
+        assert!(!flat.exists());
+        assert!(dumper.detail_path(1).exists());
+        // The flat file's old version was still found for the history.
+        assert_eq!(
+            std::fs::read_to_string(dumper.history_path(1))
+                .unwrap()
+                .lines()
+                .count(),
+            2
+        );
+        // A flat history file is moved into place before it is appended to.
+        let history = dumper.path("history").join("1.jsonl");
+        std::fs::remove_file(dumper.history_path(1)).unwrap();
+        std::fs::write(&history, "old\n").unwrap();
+        dumper.append(&dumper.history_path(1), b"new\n").unwrap();
+        assert!(!history.exists());
+        assert_eq!(
+            std::fs::read_to_string(dumper.history_path(1)).unwrap(),
+            "old\nnew\n"
+        );
+        std::fs::remove_dir_all(&dumper.out_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_unresolved_pages() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "tokenlist": [
+                    { "gid": 1, "token": "0000000001" },
+                    { "gid": 2, "error": "maximum allowed" },
+                ]
+            })))
+            .mount(&server)
+            .await;
+
+        let dumper = dumper("resolve", server.uri());
+        let refs = ["/s/aaaaaaaaaa/1-1", "/s/bbbbbbbbbb/2-3"]
+            .map(|v| v.parse::<GalleryRef>().unwrap())
+            .to_vec();
+        let (jobs, failed) = dumper.resolve(refs).await.unwrap();
+        assert_eq!(jobs, vec![(1, "0000000001".to_owned())]);
+        let [failure] = failed.as_slice() else {
+            panic!("expected one unresolved page");
+        };
+        assert_eq!(
+            (failure.gid, failure.reason.as_str()),
+            (2, "maximum allowed")
+        );
+        // The page is kept so a later run can look it up again.
+        assert_eq!(
+            failure.page.as_deref().map(str::parse::<GalleryRef>),
+            Some(Ok(GalleryRef::Page {
+                page_token: "bbbbbbbbbb".to_owned(),
+                gid: 2,
+                page: 3,
+            }))
+        );
+        std::fs::remove_dir_all(&dumper.out_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn writes_data_from_gdata() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "gmetadata": [{
+                    "gid": 1,
+                    "token": "0000000001",
+                    "title": "one",
+                    "uploader": "someone",
+                    "category": "Manga",
+                    "thumb": "https://ehgt.org/t/1.webp",
+                    "posted": "1768073020",
+                    "tags": ["language:english", "female:glasses"],
+                }]
+            })))
+            .mount(&server)
+            .await;
+
+        let dumper = dumper("data", server.uri());
+        let fetched = dumper.fetch([(1, "0000000001".to_owned())]).await.unwrap();
+        assert_eq!(fetched.dumped, 1);
+        assert!(fetched.failed.is_empty());
+        assert!(dumper.detail_path(1).exists());
+        let data: Value =
+            serde_json::from_str(&std::fs::read_to_string(dumper.data_path(1)).unwrap()).unwrap();
+        assert_eq!(
+            data,
+            json!({
+                "a": "someone",
+                "c": ["l:english", "f:glasses"],
+                "g": 1,
+                "i": "https://ehgt.org/t/1.webp",
+                "n": "[Manga] one",
+                "p": 1768073020,
+                "t": "0000000001",
+            })
+        );
+        assert!(dumper.state().has_data(1));
+        std::fs::remove_dir_all(&dumper.out_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn fetches_batches_concurrently() {
+        const DELAY: Duration = Duration::from_millis(300);
+        // When each request reached the server. Every response takes DELAY, so
+        // a request is in flight for DELAY after it arrived.
+        let arrivals = Arc::new(Mutex::new(vec![]));
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with({
+                let arrivals = arrivals.clone();
+                move |request: &Request| {
+                    arrivals.lock().unwrap().push(Instant::now());
+                    let body: Value = request.body_json().unwrap();
+                    let gmetadata = body["gidlist"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|v| json!({ "gid": v[0], "token": v[1] }))
+                        .collect::<Vec<_>>();
+                    ResponseTemplate::new(200)
+                        .set_body_json(json!({ "gmetadata": gmetadata }))
+                        .set_delay(DELAY)
+                }
+            })
+            .mount(&server)
+            .await;
+
+        let mut dumper = dumper("concurrent", server.uri());
+        dumper.batch_size = 1;
+        dumper.concurrency = 4;
+        let mut jobs = (1..=8)
+            .map(|gid| (gid, format!("{gid:010}")))
+            .collect::<Vec<_>>();
+        // Listed twice, but only fetched once.
+        jobs.push((3, format!("{:010}", 3)));
+        let fetched = dumper.fetch(jobs).await.unwrap();
+        assert_eq!(fetched.dumped, 8);
+        assert!(fetched.failed.is_empty());
+        assert_eq!(
+            layout::files(&dumper.path("detail"), "json").unwrap().len(),
+            8
+        );
+
+        let arrivals = arrivals.lock().unwrap();
+        assert_eq!(arrivals.len(), 8);
+        let in_flight = arrivals
+            .iter()
+            .map(|at| {
+                arrivals
+                    .iter()
+                    .filter(|v| *v <= at && at.duration_since(**v) < DELAY)
+                    .count()
+            })
+            .max();
+        assert_eq!(in_flight, Some(4));
+        std::fs::remove_dir_all(&dumper.out_dir).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+use std::{fmt, str::FromStr};
+
+/// A pointer to a gallery as users paste it: a gallery URL on either site, a
+/// bare `gid/token` pair or the URL of a single page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GalleryRef {
+    /// `https://e-hentai.org/g/<gid>/<token>/` or `<gid>/<token>`.
+    Gallery { gid: u64, token: String },
+    /// `https://e-hentai.org/s/<page_token>/<gid>-<page>`, needs a gtoken lookup.
+    Page {
+        page_token: String,
+        gid: u64,
+        page: u32,
+    },
+}
+
+impl GalleryRef {
+    pub fn gid(&self) -> u64 {
+        match self {
+            GalleryRef::Gallery { gid, .. } | GalleryRef::Page { gid, .. } => *gid,
+        }
+    }
+}
+
+impl fmt::Display for GalleryRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GalleryRef::Gallery { gid, token } => write!(f, "{gid}/{token}"),
+            GalleryRef::Page {
+                page_token,
+                gid,
+                page,
+            } => write!(f, "/s/{page_token}/{gid}-{page}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unrecognised gallery reference {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+const HOSTS: [&str; 5] = [
+    "e-hentai.org",
+    "www.e-hentai.org",
+    "g.e-hentai.org",
+    "exhentai.org",
+    "www.exhentai.org",
+];
+
+impl FromStr for GalleryRef {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseError(s.to_owned());
+        let value = s.trim();
+        let value = value.split(['?', '#']).next().unwrap_or_default();
+
+        let (path, url) = match value.split_once("://") {
+            Some((scheme, rest)) => {
+                if !matches!(scheme.to_ascii_lowercase().as_str(), "http" | "https") {
+                    return Err(error());
+                }
+                let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+                if !HOSTS.contains(&host.to_ascii_lowercase().as_str()) {
+                    return Err(error());
+                }
+                (path, true)
+            }
+            None => (value.trim_start_matches('/'), false),
+        };
+        let segments = path
+            .split(['/', ' ', '\t'])
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>();
+
+        match segments.as_slice() {
+            // Only a bare pair may leave out the `g`, URLs always have it.
+            ["g", gid, token] | [gid, token] if !url || segments[0] == "g" => {
+                Ok(GalleryRef::Gallery {
+                    gid: gid.parse().map_err(|_| error())?,
+                    token: parse_token(token).ok_or_else(error)?,
+                })
+            }
+            ["s", page_token, page] => {
+                let (gid, page) = page.split_once('-').ok_or_else(error)?;
+                Ok(GalleryRef::Page {
+                    page_token: parse_token(page_token).ok_or_else(error)?,
+                    gid: gid.parse().map_err(|_| error())?,
+                    page: page.parse().map_err(|_| error())?,
+                })
+            }
+            _ => Err(error()),
+        }
+    }
+}
+
+/// Gallery and page tokens are ten hex digits.
+fn parse_token(token: &str) -> Option<String> {
+    (token.len() == 10 && token.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| token.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GalleryRef;
+
+    fn gallery(gid: u64, token: &str) -> Option<GalleryRef> {
+        Some(GalleryRef::Gallery {
+            gid,
+            token: token.to_owned(),
+        })
+    }
+
+    fn page(page_token: &str, gid: u64, page: u32) -> Option<GalleryRef> {
+        Some(GalleryRef::Page {
+            page_token: page_token.to_owned(),
+            gid,
+            page,
+        })
+    }
+
+    #[test]
+    fn parse() {
+        let cases = [
+            (
+                "https://e-hentai.org/g/3731743/67c3b3ec34/",
+                gallery(3731743, "67c3b3ec34"),
+            ),
+            (
+                "https://e-hentai.org/g/3731743/67c3b3ec34",
+                gallery(3731743, "67c3b3ec34"),
+            ),
+            (
+                "http://e-hentai.org/g/3731743/67c3b3ec34/",
+                gallery(3731743, "67c3b3ec34"),
+            ),
+            (
+                "https://exhentai.org/g/3731743/67c3b3ec34/",
+                gallery(3731743, "67c3b3ec34"),
+            ),
+            (
+                "https://www.e-hentai.org/g/3731743/67c3b3ec34/",
+                gallery(3731743, "67c3b3ec34"),
+            ),
+            (
+                "https://g.e-hentai.org/g/3731743/67c3b3ec34/",
+                gallery(3731743, "67c3b3ec34"),
+            ),
+            (
+                "https://e-hentai.org/g/3731743/67c3b3ec34/?p=2",
+                gallery(3731743, "67c3b3ec34"),
+            ),
+            (
+                "https://e-hentai.org/g/3731743/67c3b3ec34/#comments",
+                gallery(3731743, "67c3b3ec34"),
+            ),
+            (
+                "HTTPS://E-HENTAI.ORG/g/3731743/67C3B3EC34/",
+                gallery(3731743, "67c3b3ec34"),
+            ),
+            (
+                "  https://e-hentai.org/g/3731743/67c3b3ec34/\n",
+                gallery(3731743, "67c3b3ec34"),
+            ),
+            ("3731743/67c3b3ec34", gallery(3731743, "67c3b3ec34")),
+            ("3731743 67c3b3ec34", gallery(3731743, "67c3b3ec34")),
+            ("/g/3731743/67c3b3ec34/", gallery(3731743, "67c3b3ec34")),
+            (
+                "https://e-hentai.org/s/0123456789/3731743-12",
+                page("0123456789", 3731743, 12),
+            ),
+            (
+                "https://exhentai.org/s/abcdef0123/3731743-1?nl=1",
+                page("abcdef0123", 3731743, 1),
+            ),
+            ("/s/abcdef0123/3731743-1", page("abcdef0123", 3731743, 1)),
+            ("", None),
+            ("3731743", None),
+            ("3731743/", None),
+            ("3731743/67c3b3ec3", None),
+            ("3731743/67c3b3ec3g", None),
+            ("-1/67c3b3ec34", None),
+            ("https://example.com/g/3731743/67c3b3ec34/", None),
+            ("ftp://e-hentai.org/g/3731743/67c3b3ec34/", None),
+            ("https://e-hentai.org/", None),
+            ("https://e-hentai.org/g/3731743/", None),
+            ("https://e-hentai.org/g/abc/67c3b3ec34/", None),
+            ("https://e-hentai.org/g/3731743/67c3b3ec34/extra", None),
+            ("https://e-hentai.org/s/abcdef0123/3731743", None),
+            ("https://e-hentai.org/s/abcdef0123/3731743-x", None),
+            ("https://e-hentai.org/t/3731743/67c3b3ec34/", None),
+            ("https://e-hentai.org/123/abcdef0123", None),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(input.parse::<GalleryRef>().ok(), expected, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for input in ["3731743/67c3b3ec34", "/s/abcdef0123/3731743-1"] {
+            let parsed = input.parse::<GalleryRef>().unwrap();
+            assert_eq!(parsed.to_string().parse::<GalleryRef>().unwrap(), parsed);
+        }
+    }
+}
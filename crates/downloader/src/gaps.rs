@@ -0,0 +1,110 @@
+use std::{
+    fs::{OpenOptions, read_to_string},
+    io::{self, ErrorKind, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dumper::write_atomically;
+
+/// The gids of the previous feed, kept between runs to check the next one against.
+#[derive(Default, Serialize, Deserialize)]
+pub struct FeedState {
+    pub gids: Vec<u64>,
+}
+
+/// Gids `from..=to` were uploaded between two polls but never showed up in
+/// the feed. One line of the backfill queue.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Gap {
+    pub from: u64,
+    pub to: u64,
+    /// Unix time the gap was noticed.
+    #[serde(default)]
+    pub found: u64,
+}
+
+impl Gap {
+    pub fn len(&self) -> u64 {
+        self.to - self.from + 1
+    }
+}
+
+/// Reads the feed state of the previous run, `None` if there was none.
+pub fn load_state(path: impl AsRef<Path>) -> io::Result<Option<FeedState>> {
+    let text = match read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(serde_json::from_str(&text).ok())
+}
+
+/// The gids that may have been missed between the `previous` feed and the
+/// `current` one. `overlaps` is whether any current entry was already known,
+/// in which case the feeds connect and nothing was missed.
+pub fn find(previous: &[u64], current: &[u64], overlaps: bool) -> Option<(u64, u64)> {
+    if overlaps || current.iter().any(|v| previous.contains(v)) {
+        return None;
+    }
+    let from = previous.iter().max()? + 1;
+    let to = current.iter().min()?.checked_sub(1)?;
+    (from <= to).then_some((from, to))
+}
+
+/// Reads the backfill queue. A missing file is an empty queue.
+pub fn load(path: impl AsRef<Path>) -> io::Result<Vec<Gap>> {
+    let text = match read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    Ok(text
+        .lines()
+        .filter(|v| !v.trim().is_empty())
+        .filter_map(|v| match serde_json::from_str(v) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                eprintln!("skipping malformed gap {v:?}: {e}");
+                None
+            }
+        })
+        .collect())
+}
+
+/// Drops `done` from the backfill queue, keeping gaps added in the meantime.
+pub fn remove(path: impl AsRef<Path>, done: &Gap) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut out = String::new();
+    for gap in load(path)?.into_iter().filter(|v| v != done) {
+        out.push_str(&serde_json::to_string(&gap)?);
+        out.push('\n');
+    }
+    write_atomically(path, out.as_bytes())
+}
+
+/// Appends `gap` to the backfill queue.
+pub fn push(path: impl AsRef<Path>, gap: &Gap) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(gap)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find;
+
+    #[test]
+    fn finds_gaps() {
+        // The feeds share a gallery.
+        assert_eq!(find(&[10, 11, 12], &[12, 13, 14], false), None);
+        // A current entry already has a data file.
+        assert_eq!(find(&[10, 11, 12], &[20, 21], true), None);
+        // Nothing in common, 13..=19 may be missing.
+        assert_eq!(find(&[10, 11, 12], &[20, 21], false), Some((13, 19)));
+        // Nothing in common, but nothing in between either.
+        assert_eq!(find(&[10, 11, 12], &[13, 14], false), None);
+        // No previous run to compare with.
+        assert_eq!(find(&[], &[20, 21], false), None);
+    }
+}
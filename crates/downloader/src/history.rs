@@ -0,0 +1,80 @@
+use ehdump_model::Change;
+use serde_json::{Map, Value};
+
+fn dumped(detail: &Map<String, Value>) -> u64 {
+    detail.get("dumped").and_then(Value::as_u64).unwrap_or(0)
+}
+
+/// The full `detail` as a first history line.
+pub fn snapshot(detail: &Map<String, Value>) -> Change {
+    Change {
+        dumped: dumped(detail),
+        set: detail
+            .iter()
+            .filter(|(k, _)| *k != "dumped")
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect(),
+        unset: vec![],
+    }
+}
+
+/// The fields that differ between two versions of a detail file, or `None`
+/// if nothing but `dumped` changed.
+pub fn diff(old: &Map<String, Value>, new: &Map<String, Value>) -> Option<Change> {
+    let set = new
+        .iter()
+        .filter(|(k, v)| *k != "dumped" && old.get(*k) != Some(*v))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect::<Map<_, _>>();
+    let unset = old
+        .keys()
+        .filter(|k| *k != "dumped" && !new.contains_key(*k))
+        .cloned()
+        .collect::<Vec<_>>();
+    (!set.is_empty() || !unset.is_empty()).then(|| Change {
+        dumped: dumped(new),
+        set,
+        unset,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ehdump_model::Change;
+    use serde_json::{Map, Value, json};
+
+    use super::{diff, snapshot};
+
+    fn map(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn diffs_fields() {
+        let old =
+            map(json!({ "dumped": 1, "title": "a", "rating": "4.5", "tags": ["x"], "error": "e" }));
+        let new = map(json!({ "dumped": 2, "title": "a", "rating": "4.6", "tags": ["x", "y"] }));
+        assert_eq!(
+            diff(&old, &new),
+            Some(Change {
+                dumped: 2,
+                set: map(json!({ "rating": "4.6", "tags": ["x", "y"] })),
+                unset: vec!["error".to_owned()],
+            })
+        );
+        let again = map(json!({ "dumped": 3, "title": "a", "rating": "4.6", "tags": ["x", "y"] }));
+        assert_eq!(diff(&new, &again), None);
+    }
+
+    #[test]
+    fn snapshot_skips_dumped() {
+        let change = snapshot(&map(json!({ "dumped": 1, "title": "a" })));
+        assert_eq!(
+            serde_json::to_string(&change).unwrap(),
+            r#"{"dumped":1,"set":{"title":"a"}}"#
+        );
+    }
+}
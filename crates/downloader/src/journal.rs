@@ -0,0 +1,36 @@
+use std::{
+    fs::read_to_string,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::rss::Item;
+
+/// The galleries an rss run is dumping. It is written before the first
+/// request and removed once every data file is written, so a journal found at
+/// startup belongs to a run that was interrupted.
+#[derive(Serialize, Deserialize)]
+pub struct Journal {
+    /// Unix time the run started. Detail files dumped after it are complete.
+    pub started: u64,
+    pub items: Vec<Item>,
+}
+
+/// Reads the journal left by an interrupted run, if there is one.
+pub fn load(path: impl AsRef<Path>) -> io::Result<Option<Journal>> {
+    let path = path.as_ref();
+    let text = match read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    match serde_json::from_str(&text) {
+        Ok(journal) => Ok(Some(journal)),
+        Err(e) => {
+            eprintln!("ignoring malformed journal {}: {e}", path.display());
+            Ok(None)
+        }
+    }
+}
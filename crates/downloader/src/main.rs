@@ -1,298 +1,776 @@
+mod api;
+mod blocked;
+mod cli;
+mod client;
+mod crawler;
+mod dumper;
+mod gallery_ref;
+mod gaps;
+mod history;
+mod journal;
+mod queue;
+mod report;
+mod retry;
+mod rss;
+mod site;
+mod state;
+
 use std::{
-    fs::{File, create_dir_all},
-    io::Write,
-    path::PathBuf,
+    collections::{HashMap, HashSet},
+    fs::{OpenOptions, create_dir_all, read_dir, read_to_string, remove_file, rename},
+    io::{self, Write},
+    path::Path,
+    pin::pin,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::Parser;
+use ehdump_model::{TagPrefix, layout};
+use reqwest::{
+    Client,
+    header::{COOKIE, HeaderMap, HeaderValue},
 };
+use serde::Deserialize;
+use serde_json::Value;
+#[cfg(unix)]
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::time::sleep;
 
-use chrono::{DateTime, Utc};
-use quick_xml::de::from_str;
+use crate::{
+    blocked::{BackOff, Blocked},
+    cli::{Cli, Command},
+    client::{RateLimitedClient, RetryPolicy},
+    dumper::{Dumper, Fetched},
+    gallery_ref::GalleryRef,
+    journal::Journal,
+    rss::fetch_data,
+    state::State,
+};
+
+const RETRY_PATH: &str = "retry.jsonl";
+const QUARANTINE_PATH: &str = "quarantine.jsonl";
+const QUEUE_PATH: &str = "requests.jsonl";
+const JOURNAL_PATH: &str = "journal.json";
+const FEED_STATE_PATH: &str = "feed_state.json";
+const GAPS_PATH: &str = "gaps.jsonl";
+const BACK_OFF_PATH: &str = "back_off.json";
+const STATE_PATH: &str = "state.jsonl";
+const REQUESTS_PER_SECOND: f64 = 1.0;
 
-use reqwest::Client;
 #[tokio::main]
-async fn main() {
-    let client = Client::new();
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let mut headers = HeaderMap::new();
+    match (&cli.ipb_member_id, &cli.ipb_pass_hash) {
+        (Some(id), Some(hash)) => {
+            let mut cookie =
+                HeaderValue::from_str(&format!("ipb_member_id={id}; ipb_pass_hash={hash}"))?;
+            cookie.set_sensitive(true);
+            headers.insert(COOKIE, cookie);
+        }
+        _ if cli.site.needs_login() => {
+            anyhow::bail!("exhentai needs --ipb-member-id and --ipb-pass-hash")
+        }
+        _ => {}
+    }
+    let dumper = Dumper {
+        client: RateLimitedClient::new(
+            Client::builder().default_headers(headers).build()?,
+            cli.requests_per_second,
+            RetryPolicy {
+                max_attempts: cli.max_attempts,
+                base_delay: Duration::from_millis(cli.base_delay),
+                max_delay: Duration::from_secs(cli.max_delay),
+            },
+        ),
+        state: Mutex::new(State::open(cli.out_dir.join(STATE_PATH), cli.dry_run)?),
+        out_dir: cli.out_dir,
+        site: cli.site,
+        api_url: cli.api_url.unwrap_or_else(|| cli.site.api_url().to_owned()),
+        rss_url: cli.rss_url.unwrap_or_else(|| cli.site.rss_url().to_owned()),
+        site_url: cli
+            .site_url
+            .unwrap_or_else(|| cli.site.site_url().to_owned()),
+        batch_size: cli.batch_size.into(),
+        concurrency: cli.concurrency.into(),
+        dry_run: cli.dry_run,
+    };
 
-    let data = fetch_data(&client)
-        .await
-        .unwrap()
-        .into_iter()
-        .map(|v| (PathBuf::from(format!("data/{}.json", v.gid)), v))
-        .filter(|v| !v.0.exists())
-        .collect::<Vec<_>>();
-    create_dir_all("detail").unwrap();
-    create_dir_all("data").unwrap();
-    for data in data.chunks(25) {
-        let payload = data
-            .iter()
-            .map(|v| (v.1.gid, v.1.token.clone()))
-            .collect::<Vec<_>>();
-        for (mut file, gid) in api(&client, payload)
-            .await
-            .unwrap()
-            .into_iter()
-            .zip(data.iter().map(|v| v.1.gid))
-        {
-            if let Value::Object(map) = &mut file {
-                map.insert(
-                    "dumped".to_string(),
-                    Value::from(
-                        std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap()
-                            .as_secs(),
-                    ),
+    let command = cli.command.unwrap_or(Command::Rss);
+    // Commands that only touch local files run during a back off too.
+    if !matches!(
+        command,
+        Command::RebuildState
+            | Command::MigrateLayout
+            | Command::Report { .. }
+            | Command::Daemon { .. }
+    ) && let Some(back_off) = BackOff::load(dumper.path(BACK_OFF_PATH))?
+        && let Some(remaining) = back_off.remaining()
+    {
+        println!(
+            "{}, leaving the site alone for another {}s",
+            back_off.reason,
+            remaining.as_secs()
+        );
+        return Ok(());
+    }
+    let result = match command {
+        Command::Rss => rss(&dumper).await,
+        Command::Fetch { gids } => fetch(&dumper, &gids).await,
+        Command::Refresh { max_age, budget } => refresh(&dumper, max_age, budget).await,
+        Command::Backfill => backfill(&dumper).await,
+        Command::Crawl { from, to } => crawl(&dumper, from.zip(to)).await,
+        Command::RebuildState => rebuild_state(&dumper),
+        Command::MigrateLayout => migrate_layout(&dumper),
+        Command::Report { kind, index } => report(&dumper, kind, index.as_deref()),
+        Command::Daemon { interval, jitter } => daemon(&dumper, interval, jitter).await,
+    };
+    // A block is not a failure of the run: it ends cleanly so the back off
+    // file gets committed and the next run knows to wait.
+    match result {
+        Err(e) => match back_off(&dumper, &e)? {
+            Some(back_off) => {
+                println!(
+                    "{}, backing off for {}s",
+                    back_off.reason,
+                    back_off.remaining().unwrap_or_default().as_secs()
                 );
+                Ok(())
             }
-            let path = PathBuf::from(format!("detail/{}.json", gid));
-            File::create(path)
-                .unwrap()
-                .write_all(serde_json::to_string(&file).unwrap().as_bytes())
-                .unwrap();
-        }
-    }
-    for (path, item) in data {
-        let t = serde_json::to_string(&item).unwrap();
-        File::create(path).unwrap().write_all(t.as_bytes()).unwrap();
+            None => Err(e),
+        },
+        Ok(()) => Ok(()),
     }
 }
 
-async fn api(client: &Client, ids: Vec<(u64, String)>) -> Result<Vec<Value>, reqwest::Error> {
-    let url = "https://api.e-hentai.org/api.php";
-    let response = client
-        .post(url)
-        .json(&json!({
-          "method": "gdata",
-          "gidlist": ids,
-          "namespace": 1
-        }))
-        .send()
-        .await?;
-    let data: Data = response.json().await?;
-    Ok(data.gmetadata)
+/// Records when to try again if `error` says the site blocked us, and returns
+/// the back off recorded.
+fn back_off(dumper: &Dumper, error: &anyhow::Error) -> anyhow::Result<Option<BackOff>> {
+    let Some(blocked) = error.downcast_ref::<Blocked>() else {
+        return Ok(None);
+    };
+    let back_off = BackOff::new(blocked);
+    dumper.write(
+        &dumper.path(BACK_OFF_PATH),
+        serde_json::to_string(&back_off)?.as_bytes(),
+    )?;
+    Ok(Some(back_off))
 }
 
-#[derive(Deserialize)]
-struct Data {
-    gmetadata: Vec<Value>,
-}
+/// Dumps the galleries new in the feed.
+async fn rss(dumper: &Dumper) -> anyhow::Result<()> {
+    let (data, quarantined): (Vec<_>, Vec<_>) =
+        fetch_data(&dumper.client, &dumper.rss_url, dumper.site)
+            .await?
+            .into_iter()
+            .partition(Result::is_ok);
+    let mut file = (!dumper.dry_run)
+        .then(|| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dumper.path(QUARANTINE_PATH))
+        })
+        .transpose()?;
+    for entry in quarantined.into_iter().filter_map(Result::err) {
+        eprintln!("quarantined {}: {}", entry.id, entry.error);
+        if let Some(file) = &mut file {
+            writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        }
+    }
+    let data = data.into_iter().filter_map(Result::ok).collect::<Vec<_>>();
+    let feed = data.iter().map(|v| v.gid).collect::<Vec<_>>();
+    let data = data
+        .into_iter()
+        .filter(|v| !dumper.state().has_data(v.gid))
+        .collect::<Vec<_>>();
+    let gap = check_coverage(dumper, &feed, data.len() < feed.len())?;
+    let mut unknown_tags = 0;
+    for tag in data.iter().flat_map(|v| &v.categories) {
+        if let TagPrefix::Unknown(namespace) = &tag.prefix {
+            eprintln!("unknown tag namespace {namespace:?} in {tag}");
+            unknown_tags += 1;
+        }
+    }
 
-#[derive(Debug)]
-pub enum Tag {
-    Other(String),
-    Female(String),
-    Male(String),
-    Mixed(String),
-    Language(String),
-    Reclass(String),
-    Parody(String),
-    Character(String),
-    Group(String),
-    Artist(String),
-    Cosplayer(String),
-    Location(String),
-    Temp(String),
-    None(String),
+    // Finish what an interrupted run left behind before starting on the feed.
+    let journal_path = dumper.path(JOURNAL_PATH);
+    let mut journal = match journal::load(&journal_path)? {
+        Some(mut journal) => {
+            journal.items.retain(|v| !dumper.state().has_data(v.gid));
+            println!(
+                "resuming {} galleries from an interrupted run",
+                journal.items.len()
+            );
+            journal
+        }
+        None => Journal {
+            started: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            items: vec![],
+        },
+    };
+    for item in data {
+        if !journal.items.iter().any(|v| v.gid == item.gid) {
+            journal.items.push(item);
+        }
+    }
+    dumper.write(&journal_path, serde_json::to_string(&journal)?.as_bytes())?;
+
+    // Previous failures are retried alongside the new galleries. Galleries
+    // the interrupted run already wrote a detail file for only need their data file.
+    let mut attempts = HashMap::new();
+    let mut jobs = journal
+        .items
+        .iter()
+        .filter(|v| {
+            dumper
+                .state()
+                .dumped(v.gid)
+                .is_none_or(|t| t < journal.started)
+        })
+        .map(|v| (v.gid, v.token.clone()))
+        .collect::<Vec<_>>();
+    // Page URLs that couldn't be resolved before get another gtoken lookup.
+    let mut pages = vec![];
+    for failure in retry::load(dumper.path(RETRY_PATH))? {
+        attempts.insert(failure.gid, failure.attempts);
+        match failure.page.as_deref().map(str::parse::<GalleryRef>) {
+            Some(Ok(page)) => pages.push(page),
+            Some(Err(e)) => eprintln!("dropping retry entry of {}: {e}", failure.gid),
+            None if !jobs.iter().any(|v| v.0 == failure.gid) => {
+                jobs.push((failure.gid, failure.token))
+            }
+            None => {}
+        }
+    }
+    let (resolved, unresolved) = dumper.resolve(pages).await?;
+    for job in resolved {
+        if !jobs.iter().any(|v| v.0 == job.0) {
+            jobs.push(job);
+        }
+    }
+
+    // Galleries from the feed get their data file from the feed, not gdata.
+    let feed = journal
+        .items
+        .iter()
+        .map(|v| (v.gid, v))
+        .collect::<HashMap<_, _>>();
+    let Fetched { dumped, mut failed } = dumper.fetch_feed(jobs, &feed).await?;
+    failed.extend(unresolved);
+    for failure in &mut failed {
+        failure.attempts += attempts.get(&failure.gid).copied().unwrap_or(0);
+    }
+    if !dumper.dry_run {
+        retry::save(dumper.path(RETRY_PATH), &failed)?;
+    }
+
+    // Failed galleries get no data file, so the next run picks them up again.
+    // The ones fetched in this run already have theirs.
+    let failed = failed.iter().map(|v| v.gid).collect::<HashSet<_>>();
+    for item in journal
+        .items
+        .iter()
+        .filter(|v| !failed.contains(&v.gid) && !dumper.state().has_data(v.gid))
+    {
+        dumper.write_data(item)?;
+    }
+    dumper.remove(&journal_path)?;
+    println!(
+        "dumped {dumped} galleries, {} queued for retry, {} tags with unknown namespaces",
+        failed.len(),
+        unknown_tags
+    );
+    match gap {
+        Some(gap) => println!(
+            "feed did not overlap the previous run, {} galleries between {} and {} queued in {GAPS_PATH}",
+            gap.len(),
+            gap.from,
+            gap.to
+        ),
+        None => println!("no coverage gap since the previous run"),
+    }
+    requests(dumper).await
 }
 
-impl Serialize for Tag {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
+/// Compares the gids in the feed with the previous run's and queues the
+/// range in between if the two don't overlap. `overlaps` is whether some feed
+/// entry was already dumped.
+fn check_coverage(
+    dumper: &Dumper,
+    feed: &[u64],
+    overlaps: bool,
+) -> anyhow::Result<Option<gaps::Gap>> {
+    let state_path = dumper.path(FEED_STATE_PATH);
+    let previous = gaps::load_state(&state_path)?.unwrap_or_default();
+    let gap = gaps::find(&previous.gids, feed, overlaps).map(|(from, to)| gaps::Gap {
+        from,
+        to,
+        found: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |v| v.as_secs()),
+    });
+    if let Some(gap) = &gap
+        && !dumper.dry_run
     {
-        let s = match self {
-            Tag::None(v) => v.clone(),
-            Tag::Artist(v) => format!("a:{v}"),
-            Tag::Character(v) => format!("c:{v}"),
-            Tag::Cosplayer(v) => format!("co:{v}"),
-            Tag::Female(v) => format!("f:{v}"),
-            Tag::Group(v) => format!("g:{v}"),
-            Tag::Language(v) => format!("l:{v}"),
-            Tag::Location(v) => format!("lo:{v}"),
-            Tag::Male(v) => format!("m:{v}"),
-            Tag::Mixed(v) => format!("mi:{v}"),
-            Tag::Other(v) => format!("o:{v}"),
-            Tag::Parody(v) => format!("p:{v}"),
-            Tag::Reclass(v) => format!("r:{v}"),
-            Tag::Temp(v) => format!("t:{v}"),
+        gaps::push(dumper.path(GAPS_PATH), gap)?;
+    }
+    if !feed.is_empty() {
+        let state = gaps::FeedState {
+            gids: feed.to_vec(),
         };
-
-        serializer.serialize_str(&s)
+        dumper.write(&state_path, serde_json::to_string(&state)?.as_bytes())?;
     }
+    Ok(gap)
 }
 
-impl From<&str> for Tag {
-    fn from(value: &str) -> Self {
-        if !value.contains(":") {
-            return Tag::None(value.to_string());
-        }
-        let (k, v) = value
-            .split_once(":")
-            .expect(&format!("Invalid tag format: {}", value));
-        let value = v.to_string();
-        match k {
-            "other" => Tag::Other(value),
-            "female" => Tag::Female(value),
-            "male" => Tag::Male(value),
-            "mixed" => Tag::Mixed(value),
-            "language" => Tag::Language(value),
-            "reclass" => Tag::Reclass(value),
-            "parody" => Tag::Parody(value),
-            "character" => Tag::Character(value),
-            "group" => Tag::Group(value),
-            "artist" => Tag::Artist(value),
-            "cosplayer" => Tag::Cosplayer(value),
-            "location" => Tag::Location(value),
-            "temp" => Tag::Temp(value),
-            _ => unimplemented!("{}", k),
+/// Does an rss run every `interval` (plus up to `jitter`) seconds, reusing the
+/// client and the state between runs. Waits out any back off the site
+/// asked for. A signal lets the current run finish and then stops.
+async fn daemon(dumper: &Dumper, interval: u64, jitter: u64) -> anyhow::Result<()> {
+    // Registered up front so a signal during a run is noticed once it is done.
+    let mut shutdown = pin!(shutdown()?);
+    loop {
+        let back_off_path = dumper.path(BACK_OFF_PATH);
+        let delay = match BackOff::load(&back_off_path)?.and_then(|v| v.remaining()) {
+            Some(remaining) => remaining,
+            None => {
+                if let Err(e) = rss(dumper).await {
+                    eprintln!("run failed: {e:#}");
+                    back_off(dumper, &e)?;
+                }
+                let interval = Duration::from_secs(interval + fastrand::u64(0..=jitter));
+                BackOff::load(&back_off_path)?
+                    .and_then(|v| v.remaining())
+                    .map_or(interval, |v| v.max(interval))
+            }
+        };
+        println!("next run in {}s", delay.as_secs());
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = &mut shutdown => break,
         }
     }
+    println!("shutting down");
+    Ok(())
 }
 
-async fn fetch_data(client: &Client) -> Result<Vec<Item>, anyhow::Error> {
-    let response = client
-        .get("https://e-hentai.org/rss/ehg.xml")
-        .send()
-        .await?;
-    let body = response.text().await?;
-    let html = Html::parse_document(&body);
-    let select = Selector::parse("p:nth-child(3)").unwrap();
-    let v = html
-        .select(&select)
-        .map(|v| {
-            let v = v.inner_html();
-            let v = v
-                .strip_prefix("Tags: ")
-                .unwrap()
-                .split_once("<br><br>Description: ")
-                .unwrap();
-            (
-                v.0.split(", ")
-                    .filter(|v| !v.trim().is_empty())
-                    .map(|v| Tag::from(v.trim()))
-                    .collect::<Vec<_>>(),
-                if v.1 == "n/t" {
-                    None
-                } else {
-                    Some(v.1.to_owned())
-                },
-            )
+/// Resolves once the daemon is asked to stop: on SIGTERM or SIGINT, or on
+/// Ctrl-C where there are no Unix signals. Listens from the moment it is called.
+fn shutdown() -> io::Result<impl Future<Output = ()>> {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        Ok(async move {
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = interrupt.recv() => {}
+            }
         })
-        .collect::<Vec<_>>();
-    let feed: Feed = from_str(&body).unwrap();
+    }
+    #[cfg(not(unix))]
+    {
+        let ctrl_c = tokio::spawn(tokio::signal::ctrl_c());
+        Ok(async move {
+            let _ = ctrl_c.await;
+        })
+    }
+}
 
-    Ok(feed
-        .entries
+/// Dumps the galleries someone added to the request queue by hand.
+async fn requests(dumper: &Dumper) -> anyhow::Result<()> {
+    let requests = queue::load(dumper.path(QUEUE_PATH))?;
+    if requests.is_empty() {
+        return Ok(());
+    }
+    let refs = requests
+        .iter()
+        .filter_map(|v| v.gallery.clone().ok())
+        .collect::<Vec<_>>();
+    let (jobs, unresolved) = dumper.resolve(refs).await?;
+    let mut failed = unresolved
         .into_iter()
-        .zip(v)
-        .map(|(v, (tags, desc))| {
-            let url = v.links[0].href.replace("https://e-hentai.org/g/", "");
-            let mut url = url.split("/");
-            let img: Div2 = serde_json::from_value(v.content.div).unwrap();
-            Item {
-                gid: url.next().unwrap().parse().unwrap(),
-                token: url.next().unwrap().to_owned(),
-                author: v.author.name,
-                name: v.title,
-                published: {
-                    let dt: DateTime<Utc> = v.updated.parse().unwrap();
-                    dt.timestamp() as u64
-                },
-                img: img.img.src,
-                description: desc,
-                categories: tags,
-            }
+        .map(|v| (v.gid, v.reason))
+        .collect::<HashMap<_, _>>();
+    failed.extend(
+        dumper
+            .fetch(jobs)
+            .await?
+            .failed
+            .into_iter()
+            .map(|v| (v.gid, v.reason)),
+    );
+    if !dumper.dry_run {
+        queue::save(dumper.path(QUEUE_PATH), &requests, &failed)?;
+    }
+    let left = requests
+        .iter()
+        .filter(|v| match &v.gallery {
+            Ok(gallery) => failed.contains_key(&gallery.gid()),
+            Err(_) => true,
         })
-        .collect())
+        .count();
+    println!(
+        "dumped {} requested galleries, {left} left in {QUEUE_PATH}",
+        requests.len() - left,
+    );
+    Ok(())
 }
 
-#[derive(Serialize)]
-pub struct Item {
-    #[serde(rename = "a")]
-    author: String,
-    #[serde(rename = "c")]
-    categories: Vec<Tag>,
-    #[serde(rename = "d", skip_serializing_if = "Option::is_none")]
-    description: Option<String>,
-    #[serde(rename = "g")]
-    gid: u64,
-    #[serde(rename = "i")]
-    img: String,
-    #[serde(rename = "n")]
-    name: String,
-    #[serde(rename = "p")]
-    published: u64,
-    #[serde(rename = "t")]
-    token: String,
+/// Dumps the galleries listed in `path`, whether or not they were dumped before.
+async fn fetch(dumper: &Dumper, path: &Path) -> anyhow::Result<()> {
+    let mut refs = vec![];
+    for line in read_to_string(path)?.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        refs.push(line.parse::<GalleryRef>()?);
+    }
+    let (jobs, unresolved) = dumper.resolve(refs).await?;
+    if !dumper.dry_run && !unresolved.is_empty() {
+        retry::push(dumper.path(RETRY_PATH), unresolved, |_| false)?;
+    }
+    dump(dumper, jobs).await
 }
 
-use scraper::{Html, Selector};
-use serde::{Deserialize, Serialize};
-use serde_json::{Value, json};
-
-#[derive(Debug, Deserialize)]
-#[serde(rename = "feed")]
-pub struct Feed {
-    #[serde(rename = "entry")]
-    entries: Vec<Entry>,
-}
-#[derive(Debug, Deserialize)]
-#[serde(rename = "entry", rename_all = "kebab-case")]
-pub struct Entry {
-    pub title: String,
-    #[serde(rename = "link")]
-    pub links: Vec<Link>,
-    pub id: String,
-    pub updated: String,
-    pub author: Author,
-    pub content: Div,
+/// Re-fetches up to `budget` galleries dumped more than `max_age` days ago,
+/// most recently posted first since those still change the most.
+async fn refresh(dumper: &Dumper, max_age: u64, budget: usize) -> anyhow::Result<()> {
+    let cutoff = SystemTime::now()
+        .duration_since(UNIX_EPOCH)?
+        .as_secs()
+        .saturating_sub(max_age * 24 * 60 * 60);
+    let (stale, jobs) = stale(&dumper.path("detail"), cutoff, budget)?;
+    println!("{stale} stale galleries, refreshing {}", jobs.len());
+    dump(dumper, jobs).await
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename = "content", rename_all = "kebab-case")]
-pub struct Div {
-    pub div: serde_json::Value,
+/// How many galleries in `dir` were dumped before `cutoff`, and up to `budget`
+/// of them to refresh: the most recently posted first, then the ones dumped
+/// longest ago. Detail files that aren't a dumped gallery, like gdata errors,
+/// are skipped with a warning.
+fn stale(dir: &Path, cutoff: u64, budget: usize) -> anyhow::Result<(usize, Vec<(u64, String)>)> {
+    #[derive(Deserialize)]
+    struct Detail {
+        gid: u64,
+        token: String,
+        dumped: u64,
+        #[serde(default)]
+        posted: Value,
+    }
+
+    let mut stale = vec![];
+    for (_, path) in layout::files(dir, "json")? {
+        let detail = read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|v| Ok(serde_json::from_str::<Detail>(&v)?));
+        let detail = match detail {
+            Ok(detail) => detail,
+            Err(e) => {
+                eprintln!("skipping {}: {e}", path.display());
+                continue;
+            }
+        };
+        if detail.dumped < cutoff {
+            // gdata sends `posted` as a string
+            let posted = match &detail.posted {
+                Value::String(v) => v.parse().unwrap_or(0),
+                v => v.as_u64().unwrap_or(0),
+            };
+            stale.push((posted, detail.dumped, detail.gid, detail.token));
+        }
+    }
+    stale.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    let count = stale.len();
+    let jobs = stale.into_iter().take(budget).map(|v| (v.2, v.3)).collect();
+    Ok((count, jobs))
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Div2 {
-    pub img: Img,
+/// Fetches details for galleries that have a data file but no detail file.
+async fn backfill(dumper: &Dumper) -> anyhow::Result<()> {
+    let gids = dumper
+        .state()
+        .records()
+        .filter(|v| v.data && v.dumped.is_none())
+        .map(|v| v.gid)
+        .collect::<Vec<_>>();
+    // Tokens are only looked up as their batch comes due.
+    let jobs = gids.into_iter().filter_map(|gid| {
+        let state = dumper.state();
+        let record = state.get(gid)?;
+        record.dumped.is_none().then(|| (gid, record.token.clone()))
+    });
+    dump(dumper, jobs).await
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename = "div", rename_all = "kebab-case")]
-pub struct Img {
-    #[serde(rename = "@src")]
-    pub src: String,
+/// Replaces the state log with a fresh scan of data/ and detail/.
+fn rebuild_state(dumper: &Dumper) -> anyhow::Result<()> {
+    let state = State::rebuild(
+        dumper.path(STATE_PATH),
+        &dumper.path("data"),
+        &dumper.path("detail"),
+    )?;
+    println!("rebuilt state of {} galleries", state.records().count());
+    Ok(())
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Link {
-    #[serde(rename = "@rel")]
-    pub rel: Option<String>,
+/// Moves the files of the flat data/, detail/ and history/ layout into their
+/// shard directories. A flat file whose sharded copy already exists is older
+/// and is deleted.
+fn migrate_layout(dumper: &Dumper) -> anyhow::Result<()> {
+    for (name, extension) in [("data", "json"), ("detail", "json"), ("history", "jsonl")] {
+        let dir = dumper.path(name);
+        let files = match read_dir(&dir) {
+            Ok(files) => files,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let (mut moved, mut removed) = (0, 0);
+        for file in files {
+            let path = file?.path();
+            let Some(gid) = layout::gid(&path, extension) else {
+                continue;
+            };
+            let target = layout::path(&dir, gid, extension);
+            if target.exists() {
+                if !dumper.dry_run {
+                    remove_file(&path)?;
+                }
+                removed += 1;
+            } else {
+                if !dumper.dry_run {
+                    create_dir_all(target.parent().unwrap())?;
+                    rename(&path, &target)?;
+                }
+                moved += 1;
+            }
+        }
+        println!("{name}: moved {moved} files, removed {removed} superseded ones");
+    }
+    Ok(())
+}
 
-    #[serde(rename = "@type")]
-    pub link_type: Option<String>,
+/// Crawls the front page for the galleries in `range`, or for each gap in the
+/// backfill queue, and dumps the ones without a detail file. Gaps are dropped
+/// from the queue once crawled.
+async fn crawl(dumper: &Dumper, range: Option<(u64, u64)>) -> anyhow::Result<()> {
+    let gaps = match range {
+        Some((from, to)) => vec![gaps::Gap { from, to, found: 0 }],
+        None => gaps::load(dumper.path(GAPS_PATH))?,
+    };
+    for gap in &gaps {
+        let found = crawler::crawl(&dumper.client, &dumper.site_url, gap.from, gap.to).await?;
+        println!(
+            "found {} galleries between {} and {}",
+            found.len(),
+            gap.from,
+            gap.to
+        );
+        let jobs = found
+            .into_iter()
+            .filter(|v| dumper.state().dumped(v.0).is_none());
+        dump(dumper, jobs).await?;
+        if range.is_none() && !dumper.dry_run {
+            gaps::remove(dumper.path(GAPS_PATH), gap)?;
+        }
+    }
+    Ok(())
+}
 
-    #[serde(rename = "@href")]
-    pub href: String,
+/// Prints the missing gid ranges, optionally only those of one `kind`, with
+/// totals per kind on stderr.
+fn report(dumper: &Dumper, kind: Option<report::Kind>, index: Option<&Path>) -> anyhow::Result<()> {
+    let mut holdings = report::Holdings::default();
+    holdings.scan_data(&dumper.path("data"))?;
+    holdings.scan_detail(&dumper.path("detail"))?;
+    let archive = dumper.path("archive");
+    if archive.is_dir() {
+        holdings.scan_archive(&archive)?;
+    }
+    if let Some(index) = index {
+        holdings.scan_index(index)?;
+    }
+    let mut out = io::stdout().lock();
+    let mut totals = HashMap::new();
+    for range in holdings
+        .ranges()
+        .into_iter()
+        .filter(|v| kind.is_none_or(|kind| v.kind == kind))
+    {
+        writeln!(out, "{}", serde_json::to_string(&range)?)?;
+        let total = totals.entry(range.kind).or_insert((0, 0));
+        total.0 += 1;
+        total.1 += range.count;
+    }
+    for (kind, (ranges, count)) in totals {
+        eprintln!("{kind:?}: {count} gids in {ranges} ranges");
+    }
+    Ok(())
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Author {
-    pub name: String,
+/// Fetches `jobs`, adds the failures to the retry queue and takes the
+/// galleries that succeeded out of it.
+async fn dump(
+    dumper: &Dumper,
+    jobs: impl IntoIterator<Item = (u64, String)>,
+) -> anyhow::Result<()> {
+    let started = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let Fetched { dumped, failed } = dumper.fetch(jobs).await?;
+    println!(
+        "dumped {dumped} galleries, {} queued for retry",
+        failed.len()
+    );
+    if !dumper.dry_run {
+        retry::push(dumper.path(RETRY_PATH), failed, |gid| {
+            dumper.state().dumped(gid).is_some_and(|t| t >= started)
+        })?;
+    }
+    Ok(())
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Summary {
-    #[serde(rename = "@type")]
-    pub summary_type: Option<String>,
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs, process, sync::Mutex};
 
-    #[serde(rename = "$text")]
-    pub text: String,
-}
+    use ehdump_model::layout;
+    use reqwest::Client;
+    use serde_json::{Value, json};
+    use wiremock::{Mock, MockServer, ResponseTemplate, matchers::method};
+
+    use super::{JOURNAL_PATH, STATE_PATH, rss, stale};
+    use crate::{
+        client::{RateLimitedClient, RetryPolicy},
+        dumper::Dumper,
+        journal::Journal,
+        rss::Item,
+        site::Site,
+        state::State,
+    };
 
-#[derive(Debug, Deserialize)]
-pub struct Content {
-    #[serde(rename = "$value")]
-    pub value: String,
+    #[tokio::test]
+    async fn finishes_interrupted_run() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<?xml version="1.0" encoding="UTF-8"?><feed xmlns="http://www.w3.org/2005/Atom"></feed>"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "gmetadata": [{
+                    "gid": 2,
+                    "token": "0000000002",
+                    "title": "from gdata",
+                    "uploader": "someone",
+                    "category": "Manga",
+                    "thumb": "",
+                    "posted": "0",
+                    "tags": [],
+                }]
+            })))
+            .mount(&server)
+            .await;
+
+        let out_dir = temp_dir().join(format!("ehdump-resume-{}", process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+        let dumper = Dumper {
+            client: RateLimitedClient::new(Client::new(), 0.0, RetryPolicy::default()),
+            state: Mutex::new(State::open(out_dir.join(STATE_PATH), false).unwrap()),
+            out_dir,
+            site: Site::EHentai,
+            api_url: server.uri(),
+            rss_url: server.uri(),
+            site_url: String::new(),
+            batch_size: 25,
+            concurrency: 1,
+            dry_run: false,
+        };
+        let item = |gid: u64| Item {
+            author: "someone".to_owned(),
+            categories: vec![],
+            description: None,
+            gid,
+            img: String::new(),
+            name: format!("gallery {gid}"),
+            published: 0,
+            token: format!("{gid:010}"),
+            site: Site::EHentai,
+        };
+        // The interrupted run got as far as the detail file of the first gallery.
+        dumper
+            .state()
+            .update(1, "0000000001", |v| v.dumped = Some(200))
+            .unwrap();
+        let journal = Journal {
+            started: 100,
+            items: vec![item(1), item(2)],
+        };
+        fs::write(
+            dumper.path(JOURNAL_PATH),
+            serde_json::to_string(&journal).unwrap(),
+        )
+        .unwrap();
+
+        rss(&dumper).await.unwrap();
+
+        assert!(!dumper.path(JOURNAL_PATH).exists());
+        // Both data files come from the feed, even the one gdata could build.
+        for gid in [1, 2] {
+            let data: Value =
+                serde_json::from_str(&fs::read_to_string(dumper.data_path(gid)).unwrap()).unwrap();
+            assert_eq!(data["n"], format!("gallery {gid}"));
+            assert!(dumper.state().has_data(gid));
+        }
+        // Only the gallery without a detail file was fetched again.
+        let requests = server.received_requests().await.unwrap();
+        let gdata = requests
+            .iter()
+            .filter(|v| v.method.as_str() == "POST")
+            .map(|v| v.body_json::<Value>().unwrap()["gidlist"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(gdata, vec![json!([[2, "0000000002"]])]);
+        fs::remove_dir_all(&dumper.out_dir).unwrap();
+    }
+
+    #[test]
+    fn picks_stale_galleries() {
+        let dir = temp_dir().join(format!("ehdump-stale-{}", process::id()));
+        let detail = |gid: u64, contents: &str| {
+            let path = layout::path(&dir, gid, "json");
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+        let gallery = |gid: u64, posted: u64, dumped: u64| {
+            detail(
+                gid,
+                &format!(
+                    r#"{{"gid":{gid},"token":"{gid:010}","posted":"{posted}","dumped":{dumped}}}"#
+                ),
+            )
+        };
+        gallery(1, 100, 10);
+        gallery(2, 300, 20);
+        gallery(3, 300, 5);
+        gallery(4, 200, 30);
+        // Dumped recently enough.
+        gallery(5, 400, 60);
+        // A gdata error and a file that isn't JSON are skipped.
+        detail(
+            6,
+            r#"{"gid":6,"error":"Key missing, or incorrect key provided."}"#,
+        );
+        detail(7, "not json");
+
+        let gids = |jobs: Vec<(u64, String)>| jobs.into_iter().map(|v| v.0).collect::<Vec<_>>();
+        let (count, jobs) = stale(&dir, 50, 10).unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(gids(jobs), vec![3, 2, 4, 1]);
+        let (count, jobs) = stale(&dir, 50, 2).unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(gids(jobs), vec![3, 2]);
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
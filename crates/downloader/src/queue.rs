@@ -0,0 +1,101 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::read_to_string,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{dumper::write_atomically, gallery_ref::GalleryRef};
+
+/// One line of the request queue, either `{"gid":..,"token":".."}`, `{"url":".."}`,
+/// a JSON string holding a gallery URL or a bare gallery URL.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Gallery { gid: u64, token: String },
+    Url { url: String },
+    Bare(String),
+}
+
+/// A line of the request queue and the gallery it asks for.
+pub struct Request {
+    line: String,
+    pub gallery: Result<GalleryRef, String>,
+}
+
+fn parse(line: &str) -> Result<GalleryRef, String> {
+    let url = match serde_json::from_str(line) {
+        Ok(Line::Gallery { gid, token }) => return Ok(GalleryRef::Gallery { gid, token }),
+        Ok(Line::Url { url } | Line::Bare(url)) => url,
+        Err(_) if !line.starts_with(['{', '[', '"']) => line.to_owned(),
+        Err(e) => return Err(format!("invalid request: {e}")),
+    };
+    url.parse()
+        .map_err(|e: crate::gallery_ref::ParseError| e.to_string())
+}
+
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// Reads the request queue. A missing file is an empty queue.
+pub fn load(path: impl AsRef<Path>) -> io::Result<Vec<Request>> {
+    let text = match read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    Ok(lines(&text)
+        .map(|line| Request {
+            line: line.to_owned(),
+            gallery: parse(line),
+        })
+        .collect())
+}
+
+/// Rewrites the queue after a run: requests without an entry in `failed` are
+/// dropped, failed ones stay with an `error` field. Lines added to the file
+/// while the run was going are kept untouched.
+pub fn save(
+    path: impl AsRef<Path>,
+    requests: &[Request],
+    failed: &HashMap<u64, String>,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut out = String::new();
+    for request in requests {
+        let reason = match &request.gallery {
+            Ok(gallery) => match failed.get(&gallery.gid()) {
+                Some(reason) => reason,
+                None => continue,
+            },
+            Err(reason) => reason,
+        };
+        let mut line = match serde_json::from_str(&request.line) {
+            Ok(Value::Object(map)) => map,
+            Ok(Value::String(url)) => Map::from_iter([("url".to_owned(), Value::from(url))]),
+            _ => Map::from_iter([("url".to_owned(), Value::from(request.line.as_str()))]),
+        };
+        line.insert("error".to_owned(), Value::from(reason.as_str()));
+        out.push_str(&serde_json::to_string(&line)?);
+        out.push('\n');
+    }
+
+    let seen = requests
+        .iter()
+        .map(|v| v.line.as_str())
+        .collect::<HashSet<_>>();
+    let current = match read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    for line in lines(&current).filter(|v| !seen.contains(v)) {
+        out.push_str(line);
+        out.push('\n');
+    }
+    write_atomically(path, out.as_bytes())
+}
@@ -0,0 +1,213 @@
+use std::{collections::BTreeMap, fs::File, io::BufReader, path::Path};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use ehdump_archive::{index::Index, reader};
+use ehdump_model::layout;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// What we hold for a gid.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Status {
+    /// Only a data file, so the feed listed it but gdata never answered.
+    NoDetail,
+    /// gdata answered with an error.
+    Error,
+    Complete,
+}
+
+/// Why a range of gids is missing.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    /// Nothing at all, not even in the feed.
+    NeverSeen,
+    /// Seen in the feed but no detail.
+    NoDetail,
+    /// The detail is an error.
+    Error,
+}
+
+/// A run of `count` missing gids `from..=to`. Written one per line, so lines
+/// of one kind can be appended to gaps.jsonl as they are.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Range {
+    pub kind: Kind,
+    pub from: u64,
+    pub to: u64,
+    pub count: u64,
+}
+
+/// Everything known about each gid, from the archive, detail/ and data/.
+#[derive(Default)]
+pub struct Holdings {
+    statuses: BTreeMap<u64, Status>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    gid: u64,
+    #[serde(default)]
+    error: Value,
+}
+
+impl Entry {
+    fn status(&self) -> Status {
+        match self.error {
+            Value::Null | Value::Bool(false) => Status::Complete,
+            _ => Status::Error,
+        }
+    }
+}
+
+impl Holdings {
+    fn insert(&mut self, gid: u64, status: Status) {
+        let entry = self.statuses.entry(gid).or_insert(status);
+        *entry = status.max(*entry);
+    }
+
+    /// Adds the gids of every data file in `dir`.
+    pub fn scan_data(&mut self, dir: &Path) -> anyhow::Result<()> {
+        for (gid, _) in layout::files(dir, "json")? {
+            self.insert(gid, Status::NoDetail);
+        }
+        Ok(())
+    }
+
+    /// Adds every detail file in `dir`.
+    pub fn scan_detail(&mut self, dir: &Path) -> anyhow::Result<()> {
+        for (_, path) in layout::files(dir, "json")? {
+            let entry: Entry = serde_json::from_reader(BufReader::new(File::open(&path)?))
+                .with_context(|| format!("reading {}", path.display()))?;
+            self.insert(entry.gid, entry.status());
+        }
+        Ok(())
+    }
+
+    /// Adds every gallery in the archive shards in `dir`, one at a time.
+    /// Files that aren't shards are skipped.
+    pub fn scan_archive(&mut self, dir: &Path) -> anyhow::Result<()> {
+        for entry in reader::stream::<Entry>(dir)? {
+            let entry = entry?;
+            self.insert(entry.gid, entry.status());
+        }
+        Ok(())
+    }
+
+    /// Adds the gids in an item_index.bin. The index doesn't know about errors,
+    /// so every gid in it counts as complete.
+    pub fn scan_index(&mut self, path: &Path) -> anyhow::Result<()> {
+        let index = Index::load(path)?.with_context(|| format!("{} not found", path.display()))?;
+        for entry in index.entries {
+            self.insert(entry.gid, Status::Complete);
+        }
+        Ok(())
+    }
+
+    /// The missing ranges between the lowest and highest gid held.
+    pub fn ranges(&self) -> Vec<Range> {
+        let mut ranges: Vec<Range> = vec![];
+        let mut push = |kind, from, to| match ranges.last_mut() {
+            Some(last) if last.kind == kind && last.to + 1 == from => {
+                last.to = to;
+                last.count += to - from + 1;
+            }
+            _ => ranges.push(Range {
+                kind,
+                from,
+                to,
+                count: to - from + 1,
+            }),
+        };
+        let mut previous = None;
+        for (&gid, &status) in &self.statuses {
+            if let Some(previous) = previous
+                && previous + 1 < gid
+            {
+                push(Kind::NeverSeen, previous + 1, gid - 1);
+            }
+            match status {
+                Status::NoDetail => push(Kind::NoDetail, gid, gid),
+                Status::Error => push(Kind::Error, gid, gid),
+                Status::Complete => {}
+            }
+            previous = Some(gid);
+        }
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs, process};
+
+    use ehdump_archive::append::Appender;
+    use serde_json::json;
+
+    use super::{Holdings, Kind, Range, Status};
+
+    #[test]
+    fn scans_archive() {
+        let dir = temp_dir().join(format!("ehdump-report-{}", process::id()));
+        let archive = dir.join("archive");
+        let mut appender = Appender::open(&archive, &dir.join("item_index.bin"), 2).unwrap();
+        for gallery in [
+            json!({ "gid": 1 }),
+            json!({ "gid": 2, "error": "Key missing" }),
+            json!({ "gid": 3, "error": false }),
+        ] {
+            appender.push(gallery).unwrap();
+        }
+        appender.finish().unwrap();
+        // Left by an interrupted append, or by hand.
+        fs::write(archive.join("archive_2.json.tmp"), "[\n{\"gid\":").unwrap();
+        fs::write(archive.join("notes.txt"), "").unwrap();
+
+        let mut holdings = Holdings::default();
+        holdings.scan_archive(&archive).unwrap();
+        assert_eq!(
+            holdings.statuses.into_iter().collect::<Vec<_>>(),
+            vec![
+                (1, Status::Complete),
+                (2, Status::Error),
+                (3, Status::Complete)
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ranges() {
+        let mut holdings = Holdings::default();
+        for (gid, status) in [
+            (10, Status::Complete),
+            (11, Status::NoDetail),
+            (12, Status::NoDetail),
+            (15, Status::Error),
+            (16, Status::Complete),
+            (20, Status::NoDetail),
+        ] {
+            holdings.insert(gid, status);
+        }
+        // A complete copy wins over the data file.
+        holdings.insert(20, Status::Complete);
+        holdings.insert(16, Status::NoDetail);
+
+        let range = |kind, from, to| Range {
+            kind,
+            from,
+            to,
+            count: to - from + 1,
+        };
+        assert_eq!(
+            holdings.ranges(),
+            vec![
+                range(Kind::NoDetail, 11, 12),
+                range(Kind::NeverSeen, 13, 14),
+                range(Kind::Error, 15, 15),
+                range(Kind::NeverSeen, 17, 19),
+            ]
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    fs::read_to_string,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dumper::write_atomically;
+
+/// Failures are dropped from the queue after this many runs.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// A gallery whose gdata lookup failed, kept so a later run can try it again.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Failure {
+    pub gid: u64,
+    pub token: String,
+    pub reason: String,
+    #[serde(default)]
+    pub attempts: u32,
+    /// The page URL of a gallery whose token gtoken couldn't look up yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<String>,
+}
+
+/// Reads the retry queue, one JSON object per line. A missing file is an empty queue.
+pub fn load(path: impl AsRef<Path>) -> io::Result<Vec<Failure>> {
+    let text = match read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    Ok(text
+        .lines()
+        .filter(|v| !v.trim().is_empty())
+        .filter_map(|v| match serde_json::from_str(v) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                eprintln!("skipping malformed retry entry {v:?}: {e}");
+                None
+            }
+        })
+        .collect())
+}
+
+/// Adds `failures` to the retry queue, replacing older entries for the same
+/// gids but carrying their attempts forward, and drops the entries of gids
+/// that `succeeded`.
+pub fn push(
+    path: impl AsRef<Path>,
+    mut failures: Vec<Failure>,
+    succeeded: impl Fn(u64) -> bool,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut queue = load(path)?;
+    let len = queue.len();
+    queue.retain(|v| !succeeded(v.gid));
+    if failures.is_empty() && queue.len() == len {
+        return Ok(());
+    }
+    for failure in &mut failures {
+        if let Some(old) = queue.iter().find(|v| v.gid == failure.gid) {
+            failure.attempts += old.attempts;
+        }
+    }
+    queue.retain(|v| !failures.iter().any(|f| f.gid == v.gid));
+    queue.extend(failures);
+    save(path, &queue)
+}
+
+/// Replaces the retry queue with `failures`, dropping the ones that ran out of attempts.
+pub fn save(path: impl AsRef<Path>, failures: &[Failure]) -> io::Result<()> {
+    let mut out = String::new();
+    for failure in failures {
+        if failure.attempts >= MAX_ATTEMPTS {
+            eprintln!(
+                "giving up on {} after {} attempts: {}",
+                failure.gid, failure.attempts, failure.reason
+            );
+            continue;
+        }
+        out.push_str(&serde_json::to_string(failure)?);
+        out.push('\n');
+    }
+    write_atomically(path.as_ref(), out.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs::remove_file, process};
+
+    use super::{Failure, MAX_ATTEMPTS, load, push};
+
+    fn failure(gid: u64) -> Failure {
+        Failure {
+            gid,
+            token: format!("{gid:010}"),
+            reason: "failed".to_owned(),
+            attempts: 1,
+            page: None,
+        }
+    }
+
+    #[test]
+    fn push_counts_attempts() {
+        let path = temp_dir().join(format!("ehdump-retry-{}.jsonl", process::id()));
+        push(&path, vec![failure(1), failure(2)], |_| false).unwrap();
+        push(&path, vec![failure(1)], |gid| gid == 2).unwrap();
+        let queue = load(&path).unwrap();
+        assert_eq!(queue.len(), 1);
+        assert_eq!((queue[0].gid, queue[0].attempts), (1, 2));
+
+        // The last attempt drops the gallery from the queue.
+        for _ in 2..MAX_ATTEMPTS {
+            push(&path, vec![failure(1)], |_| false).unwrap();
+        }
+        assert!(load(&path).unwrap().is_empty());
+        remove_file(path).unwrap();
+    }
+}
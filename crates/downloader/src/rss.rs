@@ -0,0 +1,312 @@
+use std::{collections::HashMap, fmt};
+
+use chrono::{DateTime, Utc};
+use quick_xml::de::from_str;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+
+use ehdump_model::Tag;
+
+use crate::{client::RateLimitedClient, gallery_ref::GalleryRef, site::Site};
+
+/// Why a single feed entry could not be turned into an [`Item`].
+#[derive(Debug)]
+pub enum EntryError {
+    MissingLink,
+    MissingAuthor,
+    MissingDate,
+    MissingContent,
+    BadUrl(String),
+    BadDate(String, chrono::ParseError),
+    BadImage(serde_json::Error),
+    MissingTags,
+    BadTags(String),
+}
+
+impl fmt::Display for EntryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntryError::MissingLink => write!(f, "entry has no link"),
+            EntryError::MissingAuthor => write!(f, "entry has no author"),
+            EntryError::MissingDate => write!(f, "entry has no updated date"),
+            EntryError::MissingContent => write!(f, "entry has no content"),
+            EntryError::BadUrl(url) => write!(f, "unrecognised gallery url {url:?}"),
+            EntryError::BadDate(date, e) => write!(f, "invalid date {date:?}: {e}"),
+            EntryError::BadImage(e) => write!(f, "invalid thumbnail: {e}"),
+            EntryError::MissingTags => write!(f, "entry has no tag paragraph"),
+            EntryError::BadTags(v) => write!(f, "malformed tag paragraph {v:?}"),
+        }
+    }
+}
+
+impl std::error::Error for EntryError {}
+
+/// A feed entry that failed to parse, as written to the quarantine file.
+#[derive(Debug, Serialize)]
+pub struct Quarantined {
+    pub id: String,
+    pub title: String,
+    pub href: Option<String>,
+    pub error: String,
+}
+
+impl Quarantined {
+    fn new(entry: &Entry, error: &EntryError) -> Self {
+        Self {
+            id: entry.id.clone(),
+            title: entry.title.clone(),
+            href: entry.links.first().map(|v| v.href.clone()),
+            error: error.to_string(),
+        }
+    }
+}
+
+/// Fetches the RSS feed of `site` from `url` and parses every entry on its
+/// own, so one malformed or incomplete entry only costs that gallery.
+pub async fn fetch_data(
+    client: &RateLimitedClient,
+    url: &str,
+    site: Site,
+) -> Result<Vec<Result<Item, Quarantined>>, anyhow::Error> {
+    let body = client.text(|c| c.get(url)).await?;
+    let mut paragraphs = tag_paragraphs(&body);
+    let feed: Feed = from_str(&body)?;
+
+    Ok(feed
+        .entries
+        .into_iter()
+        .map(|entry| {
+            let paragraph = paragraphs.remove(entry.id.trim());
+            parse_entry(&entry, paragraph, site).map_err(|e| Quarantined::new(&entry, &e))
+        })
+        .collect())
+}
+
+/// The tag/description paragraph of every entry, keyed by the entry id so a
+/// missing paragraph can't shift tags onto a neighbouring gallery.
+fn tag_paragraphs(body: &str) -> HashMap<String, String> {
+    let html = Html::parse_document(body);
+    let entry = Selector::parse("entry").unwrap();
+    let id = Selector::parse("id").unwrap();
+    let paragraph = Selector::parse("p:nth-child(3)").unwrap();
+    html.select(&entry)
+        .filter_map(|entry| {
+            let id = entry.select(&id).next()?.text().collect::<String>();
+            let paragraph = entry.select(&paragraph).next().map(|v| v.inner_html());
+            Some((id.trim().to_owned(), paragraph?))
+        })
+        .collect()
+}
+
+fn parse_entry(entry: &Entry, paragraph: Option<String>, site: Site) -> Result<Item, EntryError> {
+    let href = &entry
+        .links
+        .iter()
+        .find(|v| v.rel.as_deref() == Some("alternate"))
+        .or(entry.links.first())
+        .ok_or(EntryError::MissingLink)?
+        .href;
+    let Ok(GalleryRef::Gallery { gid, token }) = href.parse() else {
+        return Err(EntryError::BadUrl(href.clone()));
+    };
+    let author = entry
+        .author
+        .as_ref()
+        .and_then(|v| v.name.clone())
+        .ok_or(EntryError::MissingAuthor)?;
+    let updated = entry.updated.as_ref().ok_or(EntryError::MissingDate)?;
+    let published = updated
+        .parse::<DateTime<Utc>>()
+        .map_err(|e| EntryError::BadDate(updated.clone(), e))?;
+    let content = entry.content.as_ref().ok_or(EntryError::MissingContent)?;
+    let img: Div2 = serde_json::from_value(content.div.clone()).map_err(EntryError::BadImage)?;
+    let (tags, description) = parse_tags(&paragraph.ok_or(EntryError::MissingTags)?)?;
+    Ok(Item {
+        gid,
+        token,
+        author,
+        name: entry.title.clone(),
+        published: published.timestamp() as u64,
+        img: img.img.src,
+        description,
+        categories: tags,
+        site,
+    })
+}
+
+fn parse_tags(paragraph: &str) -> Result<(Vec<Tag>, Option<String>), EntryError> {
+    let (tags, description) = paragraph
+        .strip_prefix("Tags: ")
+        .and_then(|v| v.split_once("<br><br>Description: "))
+        .ok_or_else(|| EntryError::BadTags(paragraph.to_owned()))?;
+    Ok((
+        tags.split(", ")
+            .filter(|v| !v.trim().is_empty())
+            .map(|v| Tag::parse_long(v.trim()))
+            .collect(),
+        if description == "n/t" {
+            None
+        } else {
+            Some(description.to_owned())
+        },
+    ))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Item {
+    #[serde(rename = "a")]
+    pub author: String,
+    #[serde(rename = "c", with = "ehdump_model::tag::short")]
+    pub categories: Vec<Tag>,
+    #[serde(rename = "d", skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(rename = "g")]
+    pub gid: u64,
+    #[serde(rename = "i")]
+    pub img: String,
+    #[serde(rename = "n")]
+    pub name: String,
+    #[serde(rename = "p")]
+    pub published: u64,
+    #[serde(rename = "t")]
+    pub token: String,
+    /// Left out for e-hentai, which every older data file is from.
+    #[serde(rename = "s", default, skip_serializing_if = "Site::is_default")]
+    pub site: Site,
+}
+
+impl Item {
+    /// The data item of a gallery that didn't come from the feed, built from
+    /// its gdata entry. gdata has no description.
+    pub fn from_gdata(gdata: &serde_json::Value, site: Site) -> Result<Self, serde_json::Error> {
+        #[derive(Deserialize)]
+        struct Gdata {
+            gid: u64,
+            token: String,
+            title: String,
+            uploader: String,
+            category: String,
+            thumb: String,
+            // gdata sends `posted` as a string
+            posted: String,
+            tags: Vec<String>,
+        }
+
+        let gdata = Gdata::deserialize(gdata)?;
+        Ok(Item {
+            gid: gdata.gid,
+            token: gdata.token,
+            author: gdata.uploader,
+            // The feed puts the category in front of the title.
+            name: format!("[{}] {}", gdata.category, gdata.title),
+            published: gdata.posted.parse().unwrap_or(0),
+            img: gdata.thumb,
+            description: None,
+            categories: gdata.tags.iter().map(|v| Tag::parse_long(v)).collect(),
+            site,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename = "feed")]
+pub struct Feed {
+    #[serde(rename = "entry", default)]
+    entries: Vec<Entry>,
+}
+
+/// A feed entry with every field optional, so a missing one is reported by
+/// [`parse_entry`] for that entry instead of failing the whole feed.
+#[derive(Debug, Deserialize)]
+#[serde(rename = "entry", rename_all = "kebab-case")]
+pub struct Entry {
+    #[serde(default)]
+    pub title: String,
+    #[serde(rename = "link", default)]
+    pub links: Vec<Link>,
+    #[serde(default)]
+    pub id: String,
+    pub updated: Option<String>,
+    pub author: Option<Author>,
+    pub content: Option<Div>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename = "content", rename_all = "kebab-case")]
+pub struct Div {
+    #[serde(default)]
+    pub div: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Div2 {
+    pub img: Img,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename = "div", rename_all = "kebab-case")]
+pub struct Img {
+    #[serde(rename = "@src")]
+    pub src: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Link {
+    #[serde(rename = "@rel")]
+    pub rel: Option<String>,
+
+    #[serde(rename = "@href", default)]
+    pub href: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Author {
+    pub name: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Client;
+    use wiremock::{Mock, MockServer, ResponseTemplate, matchers::method};
+
+    use super::fetch_data;
+    use crate::{
+        client::{RateLimitedClient, RetryPolicy},
+        site::Site,
+    };
+
+    #[tokio::test]
+    async fn parses_feed() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(include_str!("../fixtures/feed.xml")),
+            )
+            .mount(&server)
+            .await;
+
+        let client = RateLimitedClient::new(Client::new(), 0.0, RetryPolicy::default());
+        let entries = fetch_data(&client, &server.uri(), Site::ExHentai)
+            .await
+            .unwrap();
+        let [Ok(item), Err(quarantined), Err(incomplete)] = entries.as_slice() else {
+            panic!("expected one item and two quarantined entries");
+        };
+        assert_eq!(item.gid, 3731743);
+        assert_eq!(item.token, "67c3b3ec34");
+        assert_eq!(item.site, Site::ExHentai);
+        assert_eq!(item.description, None);
+        assert_eq!(
+            item.categories.iter().map(|v| v.long()).collect::<Vec<_>>(),
+            ["language:english", "female:glasses"]
+        );
+        assert!(
+            serde_json::to_string(item)
+                .unwrap()
+                .contains(r#""s":"exhentai""#)
+        );
+        assert_eq!(quarantined.id, "https://exhentai.org/t/3731742/");
+        assert_eq!(incomplete.id, "https://exhentai.org/g/3731741/0123456789/");
+        assert_eq!(incomplete.error, "entry has no author");
+    }
+}
@@ -0,0 +1,47 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Which of the two sites galleries are dumped from.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize, ValueEnum)]
+pub enum Site {
+    #[default]
+    #[serde(rename = "e-hentai")]
+    #[value(name = "e-hentai")]
+    EHentai,
+    /// Needs the `ipb_member_id` and `ipb_pass_hash` cookies of an account.
+    #[serde(rename = "exhentai")]
+    #[value(name = "exhentai")]
+    ExHentai,
+}
+
+impl Site {
+    pub fn api_url(self) -> &'static str {
+        match self {
+            Site::EHentai => "https://api.e-hentai.org/api.php",
+            Site::ExHentai => "https://s.exhentai.org/api.php",
+        }
+    }
+
+    pub fn rss_url(self) -> &'static str {
+        match self {
+            Site::EHentai => "https://e-hentai.org/rss/ehg.xml",
+            Site::ExHentai => "https://exhentai.org/rss/exg.xml",
+        }
+    }
+
+    /// The front page, crawled for galleries the feed missed.
+    pub fn site_url(self) -> &'static str {
+        match self {
+            Site::EHentai => "https://e-hentai.org/",
+            Site::ExHentai => "https://exhentai.org/",
+        }
+    }
+
+    pub fn needs_login(self) -> bool {
+        self == Site::ExHentai
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Site::default()
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions, read_to_string, rename},
+    io::{self, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use ehdump_model::layout;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::rss::Item;
+
+/// What the downloader knows about one gallery.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub gid: u64,
+    pub token: String,
+    /// Whether data/<gid>.json was written from the feed.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub data: bool,
+    /// Unix time detail/<gid>.json was last written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dumped: Option<u64>,
+    /// Failed lookups since the last successful one.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub errors: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn is_false(v: &bool) -> bool {
+    !v
+}
+
+fn is_zero(v: &u32) -> bool {
+    *v == 0
+}
+
+/// Every gallery the downloader has seen, kept in memory and persisted as an
+/// append-only log of whole records where the last line for a gid wins.
+pub struct State {
+    path: PathBuf,
+    records: HashMap<u64, Record>,
+    /// `None` on a dry run, updates then only live in memory.
+    log: Option<File>,
+}
+
+impl State {
+    /// Replays the log at `path`. A missing log is rebuilt from the data and
+    /// detail directories next to it.
+    pub fn open(path: impl Into<PathBuf>, dry_run: bool) -> anyhow::Result<Self> {
+        let path = path.into();
+        let (records, lines) = match read_to_string(&path) {
+            Ok(text) => replay(&text),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let dir = path.parent().unwrap_or(Path::new("."));
+                let records = scan(&dir.join("data"), &dir.join("detail"))?;
+                eprintln!("rebuilt state of {} galleries", records.len());
+                (records, 0)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut state = Self {
+            path,
+            records,
+            log: None,
+        };
+        if !dry_run {
+            // Rewrite the log once most of it is superseded lines.
+            if lines == 0 || lines > 2 * state.records.len() + 1000 {
+                state.compact()?;
+            }
+            state.log = Some(OpenOptions::new().append(true).open(&state.path)?);
+        }
+        Ok(state)
+    }
+
+    /// Replaces the log with a fresh scan of `data` and `detail`.
+    pub fn rebuild(path: impl Into<PathBuf>, data: &Path, detail: &Path) -> anyhow::Result<Self> {
+        let mut state = Self {
+            path: path.into(),
+            records: scan(data, detail)?,
+            log: None,
+        };
+        state.compact()?;
+        Ok(state)
+    }
+
+    pub fn get(&self, gid: u64) -> Option<&Record> {
+        self.records.get(&gid)
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.records.values()
+    }
+
+    pub fn has_data(&self, gid: u64) -> bool {
+        self.get(gid).is_some_and(|v| v.data)
+    }
+
+    pub fn dumped(&self, gid: u64) -> Option<u64> {
+        self.get(gid)?.dumped
+    }
+
+    /// Changes the record of `gid`, creating it if needed, and logs the result.
+    pub fn update(
+        &mut self,
+        gid: u64,
+        token: &str,
+        change: impl FnOnce(&mut Record),
+    ) -> io::Result<()> {
+        let record = self.records.entry(gid).or_insert_with(|| Record {
+            gid,
+            ..Default::default()
+        });
+        record.token = token.to_owned();
+        change(record);
+        if let Some(log) = &mut self.log {
+            writeln!(log, "{}", serde_json::to_string(record)?)?;
+        }
+        Ok(())
+    }
+
+    /// Writes one line per gallery to a temporary file and renames it over the log.
+    fn compact(&mut self) -> io::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut records = self.records.values().collect::<Vec<_>>();
+        records.sort_unstable_by_key(|v| v.gid);
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        for record in records {
+            writeln!(out, "{}", serde_json::to_string(record)?)?;
+        }
+        out.into_inner()?.sync_all()?;
+        rename(&tmp, &self.path)?;
+        if self.log.is_some() {
+            self.log = Some(OpenOptions::new().append(true).open(&self.path)?);
+        }
+        Ok(())
+    }
+}
+
+/// The records in a log and how many lines it had.
+fn replay(text: &str) -> (HashMap<u64, Record>, usize) {
+    let mut records = HashMap::new();
+    let mut lines = 0;
+    for line in text.lines().filter(|v| !v.trim().is_empty()) {
+        lines += 1;
+        match serde_json::from_str::<Record>(line) {
+            Ok(record) => {
+                records.insert(record.gid, record);
+            }
+            // A crash can leave half a line at the end.
+            Err(e) => eprintln!("skipping malformed state line {line:?}: {e}"),
+        }
+    }
+    (records, lines)
+}
+
+/// Builds records from what is on disk. A detail file holding a gdata error
+/// counts as a failed lookup rather than a dump.
+fn scan(data: &Path, detail: &Path) -> anyhow::Result<HashMap<u64, Record>> {
+    #[derive(Deserialize)]
+    struct Detail {
+        gid: u64,
+        #[serde(default)]
+        token: Option<String>,
+        #[serde(default)]
+        dumped: Option<u64>,
+        #[serde(default)]
+        error: Value,
+    }
+
+    let mut records = HashMap::<u64, Record>::new();
+    for (_, path) in layout::files(data, "json")? {
+        let item: Item = serde_json::from_str(&read_to_string(&path)?)
+            .with_context(|| format!("reading {}", path.display()))?;
+        let record = records.entry(item.gid).or_default();
+        record.gid = item.gid;
+        record.token = item.token;
+        record.data = true;
+    }
+    for (_, path) in layout::files(detail, "json")? {
+        let detail: Detail = serde_json::from_str(&read_to_string(&path)?)
+            .with_context(|| format!("reading {}", path.display()))?;
+        let record = records.entry(detail.gid).or_default();
+        record.gid = detail.gid;
+        if let Some(token) = detail.token {
+            record.token = token;
+        }
+        match detail.error {
+            Value::Null | Value::Bool(false) => {
+                record.dumped = Some(detail.dumped.unwrap_or_default());
+            }
+            error => {
+                record.errors = 1;
+                record.error = Some(match error {
+                    Value::String(error) => error,
+                    error => error.to_string(),
+                });
+            }
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs, path::PathBuf, process};
+
+    use ehdump_model::layout;
+
+    use super::{Record, State};
+
+    #[test]
+    fn replays_log() {
+        let dir = temp_dir().join(format!("ehdump-state-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.jsonl");
+
+        let mut state = State::open(&path, false).unwrap();
+        state.update(1, "aaaaaaaaaa", |v| v.data = true).unwrap();
+        state
+            .update(2, "bbbbbbbbbb", |v| {
+                v.errors += 1;
+                v.error = Some("Key missing".to_owned());
+            })
+            .unwrap();
+        state
+            .update(1, "aaaaaaaaaa", |v| v.dumped = Some(10))
+            .unwrap();
+        drop(state);
+
+        let state = State::open(&path, false).unwrap();
+        assert_eq!(
+            state.get(1),
+            Some(&Record {
+                gid: 1,
+                token: "aaaaaaaaaa".to_owned(),
+                data: true,
+                dumped: Some(10),
+                ..Default::default()
+            })
+        );
+        assert!(!state.has_data(2));
+        assert_eq!(state.get(2).unwrap().errors, 1);
+        assert_eq!(state.dumped(3), None);
+
+        // Dry runs don't touch the log.
+        let mut state = State::open(&path, true).unwrap();
+        state.update(3, "cccccccccc", |v| v.data = true).unwrap();
+        assert!(State::open(&path, true).unwrap().get(3).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rebuilds_with_error_details() {
+        let dir = temp_dir().join(format!("ehdump-state-scan-{}", process::id()));
+        let data = dir.join("data");
+        let detail = dir.join("detail");
+        let write = |path: PathBuf, contents: &str| {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+        write(
+            layout::path(&detail, 1, "json"),
+            r#"{"gid":1,"token":"aaaaaaaaaa","dumped":10}"#,
+        );
+        write(
+            layout::path(&detail, 2, "json"),
+            r#"{"gid":2,"error":"Key missing, or incorrect key provided."}"#,
+        );
+        write(
+            layout::path(&data, 2, "json"),
+            r#"{"a":"","c":[],"g":2,"i":"","n":"","p":0,"t":"bbbbbbbbbb"}"#,
+        );
+
+        // A missing log is rebuilt when the state is opened.
+        let state = State::open(dir.join("state.jsonl"), false).unwrap();
+        assert_eq!(state.dumped(1), Some(10));
+        assert_eq!(
+            state.get(2),
+            Some(&Record {
+                gid: 2,
+                token: "bbbbbbbbbb".to_owned(),
+                data: true,
+                dumped: None,
+                errors: 1,
+                error: Some("Key missing, or incorrect key provided.".to_owned()),
+            })
+        );
+        let state = State::rebuild(dir.join("state.jsonl"), &data, &detail).unwrap();
+        assert_eq!(state.get(2).unwrap().errors, 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+
+/// Gallery category, serialized with the display name gdata uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Category {
+    Doujinshi = 0,
+    Manga = 1,
+    ArtistCG = 2,
+    GameCG = 3,
+    Western = 4,
+    NonH = 5,
+    ImageSet = 6,
+    Cosplay = 7,
+    AsianPorn = 8,
+    Misc = 9,
+    Private = 10,
+}
+
+impl Category {
+    pub const ALL: [Category; 11] = [
+        Category::Doujinshi,
+        Category::Manga,
+        Category::ArtistCG,
+        Category::GameCG,
+        Category::Western,
+        Category::NonH,
+        Category::ImageSet,
+        Category::Cosplay,
+        Category::AsianPorn,
+        Category::Misc,
+        Category::Private,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Doujinshi => "Doujinshi",
+            Category::Manga => "Manga",
+            Category::ArtistCG => "Artist CG",
+            Category::GameCG => "Game CG",
+            Category::Western => "Western",
+            Category::NonH => "Non-H",
+            Category::ImageSet => "Image Set",
+            Category::Cosplay => "Cosplay",
+            Category::AsianPorn => "Asian Porn",
+            Category::Misc => "Misc",
+            Category::Private => "private",
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownCategory(pub String);
+
+impl fmt::Display for UnknownCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown category {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownCategory {}
+
+impl FromStr for Category {
+    type Err = UnknownCategory;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|v| v.name() == s)
+            .ok_or_else(|| UnknownCategory(s.to_owned()))
+    }
+}
+
+impl TryFrom<u8> for Category {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::ALL.get(value as usize).copied().ok_or(value)
+    }
+}
+
+impl Serialize for Category {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for Category {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = <String>::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Category;
+
+    #[test]
+    fn round_trip() {
+        for category in Category::ALL {
+            let json = serde_json::to_string(&category).unwrap();
+            assert_eq!(serde_json::from_str::<Category>(&json).unwrap(), category);
+            assert_eq!(category.to_string().parse::<Category>().unwrap(), category);
+            assert_eq!(Category::try_from(category as u8), Ok(category));
+        }
+        assert_eq!(
+            serde_json::to_string(&Category::ArtistCG).unwrap(),
+            "\"Artist CG\""
+        );
+        assert!("Artist Cg".parse::<Category>().is_err());
+        assert!(Category::try_from(11).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::str::FromStr;
+
+use crate::{Category, Tag};
+use serde::{Deserialize, Deserializer, Serialize, de};
+use serde_json::{Map, Value};
+
+/// A gallery as gdata returns it, plus when it was dumped. This is what
+/// detail/ files and archive shards hold.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Root1 {
+    pub error: Option<bool>,
+    pub category: Category,
+    pub dumped: u64,
+    #[serde(default, deserialize_with = "from_optional_string")]
+    pub current_gid: Option<u64>,
+    pub current_key: Option<String>,
+    pub expunged: bool,
+    #[serde(deserialize_with = "from_string")]
+    pub filecount: u32,
+    pub filesize: u64,
+    #[serde(default, deserialize_with = "from_optional_string")]
+    pub first_gid: Option<u64>,
+    pub first_key: Option<String>,
+    pub gid: u64,
+    #[serde(default, deserialize_with = "from_optional_string")]
+    pub parent_gid: Option<u64>,
+    pub parent_key: Option<String>,
+    #[serde(deserialize_with = "from_string")]
+    pub posted: u64,
+    #[serde(deserialize_with = "from_string")]
+    pub rating: f64,
+    pub tags: Vec<Tag>,
+    pub thumb: String,
+    pub title: String,
+    #[serde(deserialize_with = "empty_string_as_none")]
+    pub title_jpn: Option<String>,
+    pub token: String,
+    #[serde(deserialize_with = "from_string")]
+    pub torrentcount: u32,
+    pub torrents: Vec<Torrents1>,
+    pub uploader: String,
+}
+
+/// One line of `history/<shard>/<gid>.jsonl`. The first line of a file sets
+/// every field of the oldest known version of a detail file, later lines only
+/// what changed since.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    pub dumped: u64,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub set: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unset: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Torrents1 {
+    #[serde(deserialize_with = "from_string")]
+    pub added: u64,
+    #[serde(deserialize_with = "from_string")]
+    pub fsize: u64,
+    pub hash: Box<str>,
+    pub name: Option<Box<str>>,
+    #[serde(deserialize_with = "from_string")]
+    pub tsize: u64,
+}
+
+fn from_optional_string<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = Value::deserialize(deserializer)?;
+
+    match value {
+        Value::Null => Ok(None),
+        Value::String(s) => T::from_str(&s).map(Some).map_err(de::Error::custom),
+        Value::Number(number) => T::from_str(&number.to_string())
+            .map(Some)
+            .map_err(de::Error::custom),
+        other => Err(de::Error::invalid_type(
+            unexpected(&other),
+            &"a number or a string holding one",
+        )),
+    }
+}
+fn from_string<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = Value::deserialize(deserializer)?;
+    match value {
+        Value::String(s) => T::from_str(&s).map_err(de::Error::custom),
+        Value::Number(number) => T::from_str(&number.to_string()).map_err(de::Error::custom),
+        other => Err(de::Error::invalid_type(
+            unexpected(&other),
+            &"a number or a string holding one",
+        )),
+    }
+}
+
+fn unexpected(value: &Value) -> de::Unexpected<'_> {
+    match value {
+        Value::Null => de::Unexpected::Unit,
+        Value::Bool(v) => de::Unexpected::Bool(*v),
+        Value::Number(_) => de::Unexpected::Other("number"),
+        Value::String(v) => de::Unexpected::Str(v),
+        Value::Array(_) => de::Unexpected::Seq,
+        Value::Object(_) => de::Unexpected::Map,
+    }
+}
+
+pub fn empty_string_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let opt = Option::<String>::deserialize(deserializer)?;
+
+    match opt {
+        None => Ok(None),
+        Some(s) if s.trim().is_empty() => Ok(None),
+        Some(s) => T::from_str(&s).map(Some).map_err(serde::de::Error::custom),
+    }
+}
@@ -0,0 +1,137 @@
+//! Where per-gallery files live. `detail/3731743.json` is stored as
+//! `detail/3731/3731743.json` so no directory holds more than a thousand
+//! files. Readers also accept the old flat layout until it is migrated.
+
+use std::{
+    collections::HashMap,
+    fs::read_dir,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// Galleries per shard directory.
+pub const SHARD_SIZE: u64 = 1000;
+
+/// The shard directory name of `gid`.
+pub fn shard(gid: u64) -> String {
+    (gid / SHARD_SIZE).to_string()
+}
+
+/// `dir/<shard>/<gid>.<extension>`.
+pub fn path(dir: &Path, gid: u64, extension: &str) -> PathBuf {
+    dir.join(shard(gid)).join(format!("{gid}.{extension}"))
+}
+
+/// Where `path` was kept in the flat layout, if it is a sharded
+/// `dir/<shard>/<gid>.<extension>` path.
+pub fn flat(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?;
+    let gid: u64 = Path::new(name).file_stem()?.to_str()?.parse().ok()?;
+    let parent = path.parent()?;
+    if parent.file_name()? != shard(gid).as_str() {
+        return None;
+    }
+    Some(parent.parent()?.join(name))
+}
+
+/// The existing file of `gid` in `dir`, sharded or still flat, or the sharded
+/// path if there is none.
+pub fn find(dir: &Path, gid: u64, extension: &str) -> PathBuf {
+    let path = path(dir, gid, extension);
+    match flat(&path) {
+        Some(flat) if !path.exists() && flat.exists() => flat,
+        _ => path,
+    }
+}
+
+/// The gid a `<gid>.<extension>` file is for.
+pub fn gid(path: &Path, extension: &str) -> Option<u64> {
+    if path.extension().is_none_or(|v| v != extension) {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+/// Every `<gid>.<extension>` file in `dir` and its shard directories, with its
+/// gid. A gid with both a sharded and a stale flat file only gets the sharded
+/// one. Anything else, like temporary files, is skipped. A missing directory
+/// has no files.
+pub fn files(dir: &Path, extension: &str) -> io::Result<Vec<(u64, PathBuf)>> {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut files = HashMap::new();
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            for entry in read_dir(&path)? {
+                let path = entry?.path();
+                if let Some(gid) = gid(&path, extension) {
+                    files.insert(gid, path);
+                }
+            }
+        } else if let Some(gid) = gid(&path, extension) {
+            files.entry(gid).or_insert(path);
+        }
+    }
+    Ok(files.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env::temp_dir,
+        fs::{create_dir_all, remove_dir_all, write},
+        path::Path,
+        process,
+    };
+
+    use super::{files, find, flat, path};
+
+    #[test]
+    fn finds_both_layouts() {
+        let dir = temp_dir().join(format!("ehdump-layout-{}", process::id()));
+        assert_eq!(
+            path(&dir, 3731743, "json"),
+            dir.join("3731").join("3731743.json")
+        );
+        assert_eq!(
+            flat(&path(&dir, 3731743, "json")),
+            Some(dir.join("3731743.json"))
+        );
+        assert_eq!(flat(&dir.join("retry.jsonl")), None);
+        assert_eq!(flat(&dir.join("3731743.json")), None);
+        assert!(files(&dir, "json").unwrap().is_empty());
+
+        create_dir_all(dir.join("3731")).unwrap();
+        for name in [
+            "3731/3731743.json",
+            // A stale flat copy loses to the sharded file.
+            "3731743.json",
+            "3731/3731744.json.tmp",
+            "12.json",
+            "notes.txt",
+        ] {
+            write(dir.join(name), "").unwrap();
+        }
+        let mut found = files(&dir, "json").unwrap();
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                (12, dir.join("12.json")),
+                (3731743, dir.join(Path::new("3731/3731743.json"))),
+            ]
+        );
+        assert_eq!(find(&dir, 12, "json"), dir.join("12.json"));
+        assert_eq!(
+            find(&dir, 3731743, "json"),
+            dir.join(Path::new("3731/3731743.json"))
+        );
+        assert_eq!(find(&dir, 13, "json"), dir.join(Path::new("0/13.json")));
+        remove_dir_all(dir).unwrap();
+    }
+}
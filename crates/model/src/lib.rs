@@ -0,0 +1,10 @@
+//! Types and the on-disk layout shared by the downloader and db-creator.
+
+mod category;
+mod gallery;
+pub mod layout;
+pub mod tag;
+
+pub use category::{Category, UnknownCategory};
+pub use gallery::{Change, Root1, Torrents1};
+pub use tag::{Tag, TagPrefix};
@@ -0,0 +1,309 @@
+use std::{borrow::Cow, fmt};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A tag namespace. Gdata and archive files spell namespaces out (`female:`),
+/// data files use the short form (`f:`).
+///
+/// An unknown namespace is kept as it is in the long form, and behind a `?` in
+/// the short form so it can't be read back as a known short name, `co` as
+/// cosplayer for example.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TagPrefix {
+    Other,
+    Female,
+    Male,
+    Mixed,
+    Language,
+    Reclass,
+    Parody,
+    Character,
+    Group,
+    Artist,
+    Cosplayer,
+    Location,
+    Temp,
+    /// A tag without namespace.
+    None,
+    /// A namespace this crate doesn't know yet, kept verbatim.
+    Unknown(String),
+}
+
+/// Marks an unknown namespace in the short form.
+const UNKNOWN: &str = "?";
+
+impl TagPrefix {
+    /// Every namespace with a long and short name.
+    pub const KNOWN: [TagPrefix; 13] = [
+        TagPrefix::Other,
+        TagPrefix::Female,
+        TagPrefix::Male,
+        TagPrefix::Mixed,
+        TagPrefix::Language,
+        TagPrefix::Reclass,
+        TagPrefix::Parody,
+        TagPrefix::Character,
+        TagPrefix::Group,
+        TagPrefix::Artist,
+        TagPrefix::Cosplayer,
+        TagPrefix::Location,
+        TagPrefix::Temp,
+    ];
+
+    /// The canonical namespace table as `(long, short)`.
+    fn names(&self) -> Option<(&'static str, &'static str)> {
+        Some(match self {
+            TagPrefix::Other => ("other", "o"),
+            TagPrefix::Female => ("female", "f"),
+            TagPrefix::Male => ("male", "m"),
+            TagPrefix::Mixed => ("mixed", "mi"),
+            TagPrefix::Language => ("language", "l"),
+            TagPrefix::Reclass => ("reclass", "r"),
+            TagPrefix::Parody => ("parody", "p"),
+            TagPrefix::Character => ("character", "c"),
+            TagPrefix::Group => ("group", "g"),
+            TagPrefix::Artist => ("artist", "a"),
+            TagPrefix::Cosplayer => ("cosplayer", "co"),
+            TagPrefix::Location => ("location", "lo"),
+            TagPrefix::Temp => ("temp", "t"),
+            TagPrefix::None | TagPrefix::Unknown(_) => return None,
+        })
+    }
+
+    /// The namespace as written in front of a long form tag, `None` for bare tags.
+    pub fn long(&self) -> Option<&str> {
+        match self {
+            TagPrefix::Unknown(namespace) => Some(namespace),
+            _ => self.names().map(|v| v.0),
+        }
+    }
+
+    /// The namespace as written in front of a short form tag, `None` for bare tags.
+    pub fn short(&self) -> Option<Cow<'_, str>> {
+        match self {
+            TagPrefix::Unknown(namespace) => Some(format!("{UNKNOWN}{namespace}").into()),
+            _ => self.names().map(|v| v.1.into()),
+        }
+    }
+
+    pub fn from_long(namespace: &str) -> Self {
+        Self::KNOWN
+            .into_iter()
+            .find(|v| v.names().is_some_and(|v| v.0 == namespace))
+            .unwrap_or_else(|| TagPrefix::Unknown(namespace.to_owned()))
+    }
+
+    pub fn from_short(namespace: &str) -> Self {
+        if let Some(namespace) = namespace.strip_prefix(UNKNOWN) {
+            return TagPrefix::Unknown(namespace.to_owned());
+        }
+        Self::KNOWN
+            .into_iter()
+            .find(|v| v.names().is_some_and(|v| v.1 == namespace))
+            .unwrap_or_else(|| TagPrefix::Unknown(namespace.to_owned()))
+    }
+
+    /// Compact id for in-memory tables. Every unknown namespace maps to `u8::MAX`.
+    pub fn id(&self) -> u8 {
+        match self {
+            TagPrefix::Other => 0,
+            TagPrefix::Female => 1,
+            TagPrefix::Male => 2,
+            TagPrefix::Mixed => 3,
+            TagPrefix::Language => 4,
+            TagPrefix::Reclass => 5,
+            TagPrefix::Parody => 6,
+            TagPrefix::Character => 7,
+            TagPrefix::Group => 8,
+            TagPrefix::Artist => 9,
+            TagPrefix::Cosplayer => 10,
+            TagPrefix::Location => 11,
+            TagPrefix::Temp => 12,
+            TagPrefix::None => 13,
+            TagPrefix::Unknown(_) => u8::MAX,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Tag {
+    pub tag: String,
+    pub prefix: TagPrefix,
+}
+
+impl Tag {
+    /// Parses `female:glasses`.
+    pub fn parse_long(value: &str) -> Self {
+        Self::parse(value, TagPrefix::from_long)
+    }
+
+    /// Parses `f:glasses`.
+    pub fn parse_short(value: &str) -> Self {
+        Self::parse(value, TagPrefix::from_short)
+    }
+
+    fn parse(value: &str, prefix: fn(&str) -> TagPrefix) -> Self {
+        let Some((k, v)) = value.split_once(":") else {
+            return Tag {
+                tag: value.to_string(),
+                prefix: TagPrefix::None,
+            };
+        };
+        Tag {
+            tag: v.to_string(),
+            prefix: prefix(k),
+        }
+    }
+
+    pub fn long(&self) -> String {
+        self.encode(self.prefix.long())
+    }
+
+    pub fn short(&self) -> String {
+        self.encode(self.prefix.short().as_deref())
+    }
+
+    fn encode(&self, prefix: Option<&str>) -> String {
+        match prefix {
+            Some(prefix) => format!("{}:{}", prefix, self.tag),
+            None => self.tag.clone(),
+        }
+    }
+}
+
+impl From<&str> for Tag {
+    fn from(value: &str) -> Self {
+        Tag::parse_long(value)
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.long())
+    }
+}
+
+/// Tags (de)serialize in long form by default.
+impl<'de> Deserialize<'de> for Tag {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = <String>::deserialize(deserializer)?;
+        Ok(Tag::parse_long(&s))
+    }
+}
+
+impl Serialize for Tag {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.long())
+    }
+}
+
+/// Short form for a list of tags, for use with `#[serde(with = "ehdump_model::tag::short")]`.
+pub mod short {
+    use serde::{Deserialize, Deserializer, Serializer, ser::SerializeSeq};
+
+    use super::Tag;
+
+    pub fn serialize<S>(tags: &[Tag], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(tags.len()))?;
+        for tag in tags {
+            seq.serialize_element(&tag.short())?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Tag>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let tags = Vec::<String>::deserialize(deserializer)?;
+        Ok(tags.iter().map(|v| Tag::parse_short(v)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::{Tag, TagPrefix};
+
+    #[derive(Serialize, Deserialize)]
+    struct Short {
+        #[serde(with = "super::short")]
+        tags: Vec<Tag>,
+    }
+
+    fn all_tags() -> Vec<Tag> {
+        TagPrefix::KNOWN
+            .into_iter()
+            .chain([
+                TagPrefix::None,
+                TagPrefix::Unknown("newspace".to_owned()),
+                // Unknown in the long form, but a known short name.
+                TagPrefix::Unknown("co".to_owned()),
+                TagPrefix::Unknown("f".to_owned()),
+            ])
+            .map(|prefix| Tag {
+                tag: "big thing".to_owned(),
+                prefix,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn namespace_names_are_unique() {
+        for a in TagPrefix::KNOWN {
+            for b in TagPrefix::KNOWN {
+                if a != b {
+                    assert_ne!(a.long(), b.long());
+                    assert_ne!(a.short(), b.short());
+                    assert_ne!(a.id(), b.id());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn long_round_trip() {
+        let tags = all_tags();
+        let json = serde_json::to_string(&tags).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<Tag>>(&json).unwrap(), tags);
+    }
+
+    #[test]
+    fn short_round_trip() {
+        let tags = Short { tags: all_tags() };
+        let json = serde_json::to_string(&tags).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Short>(&json).unwrap().tags,
+            tags.tags
+        );
+    }
+
+    #[test]
+    fn encodings() {
+        let tag = Tag::parse_long("female:big breasts");
+        assert_eq!(tag.prefix, TagPrefix::Female);
+        assert_eq!(tag.short(), "f:big breasts");
+        assert_eq!(Tag::parse_short("mi:group"), Tag::parse_long("mixed:group"));
+        assert_eq!(
+            Tag::parse_long("co:x").prefix,
+            TagPrefix::Unknown("co".into())
+        );
+        assert_eq!(Tag::parse_long("bare").short(), "bare");
+        assert_eq!(Tag::parse_long("newspace:x").short(), "?newspace:x");
+        // Older data files have unknown namespaces without the marker.
+        assert_eq!(
+            Tag::parse_short("newspace:x"),
+            Tag::parse_long("newspace:x")
+        );
+    }
+}